@@ -0,0 +1,89 @@
+// Tiempo de conversión de un frame de 24 MP con cada conversor. Se corre con
+// `cargo bench --bench conversion`. Como referencia se mide también una
+// conversión escalar pixel por pixel contra el conversor nativo en un solo
+// hilo.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::frame;
+use image::RgbImage;
+use timelapse_lego::conversion::{Color, Conversor, Convertidor, Matriz, Rango};
+
+const WIDTH: u32 = 6000;
+const HEIGHT: u32 = 4000;
+const REPETICIONES: u32 = 10;
+
+fn medir(nombre: &str, mut convertir: impl FnMut()) {
+    // La primera vuelta calienta cachés y el pool de hilos
+    convertir();
+    let mut total = Duration::ZERO;
+    for _ in 0..REPETICIONES {
+        let inicio = Instant::now();
+        convertir();
+        total += inicio.elapsed();
+    }
+    println!("{:<28} {:>8.2} ms/frame", nombre, total.as_secs_f64() * 1_000.0 / REPETICIONES as f64);
+}
+
+// BT.709 de rango limitado a 4:2:0 con índices en cada muestra, sin bloques
+fn escalar(img: &RgbImage, f: &mut frame::Video) {
+    let (w, h) = (WIDTH as usize, HEIGHT as usize);
+    let rgb = img.as_raw();
+    let fijo = |x: f64| (x * 65_536.0).round() as i32;
+    let y = [fijo(0.2126 * 219.0 / 255.0), fijo(0.7152 * 219.0 / 255.0), fijo(0.0722 * 219.0 / 255.0)];
+    let u = [fijo(-0.1146 * 224.0 / 255.0), fijo(-0.3854 * 224.0 / 255.0), fijo(0.5 * 224.0 / 255.0)];
+    let v = [fijo(0.5 * 224.0 / 255.0), fijo(-0.4542 * 224.0 / 255.0), fijo(-0.0458 * 224.0 / 255.0)];
+    let pixel = |x: usize, y: usize, c: usize| rgb[(y * w + x) * 3 + c] as i32;
+
+    let stride = f.stride(0);
+    let plano = f.data_mut(0);
+    for fila in 0..h {
+        for x in 0..w {
+            let suma = y[0] * pixel(x, fila, 0) + y[1] * pixel(x, fila, 1) + y[2] * pixel(x, fila, 2);
+            plano[fila * stride + x] = ((suma + (16 << 16) + (1 << 15)) >> 16).clamp(0, 255) as u8;
+        }
+    }
+    for (n, coeficientes) in [(1, u), (2, v)] {
+        let stride = f.stride(n);
+        let plano = f.data_mut(n);
+        for fila in 0..h / 2 {
+            for x in 0..w / 2 {
+                let mut suma = 0;
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    for (c, coeficiente) in coeficientes.iter().enumerate() {
+                        suma += coeficiente * pixel(x * 2 + dx, fila * 2 + dy, c);
+                    }
+                }
+                plano[fila * stride + x] = (((suma >> 2) + (128 << 16) + (1 << 15)) >> 16).clamp(0, 255) as u8;
+            }
+        }
+    }
+}
+
+fn main() {
+    let img = RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8])
+    });
+    let color = Color { matriz: Matriz::Bt709, rango: Rango::Limitado };
+
+    let un_hilo = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let mut f = frame::Video::new(Pixel::YUV420P, WIDTH, HEIGHT);
+    medir("Escalar YUV420P (1 hilo)", || escalar(black_box(&img), &mut f));
+    let mut convertidor = Convertidor::new(Conversor::Nativo, Pixel::YUV420P, WIDTH, HEIGHT, color).unwrap();
+    medir("Nativo YUV420P (1 hilo)", || {
+        let f: frame::Video = un_hilo.install(|| convertidor.convertir(0, black_box(&img))).unwrap();
+        black_box(f);
+    });
+
+    for pixel in [Pixel::YUV420P, Pixel::YUV422P10LE] {
+        for conversor in [Conversor::Nativo, Conversor::Swscale] {
+            let mut convertidor = Convertidor::new(conversor, pixel, WIDTH, HEIGHT, color).unwrap();
+            medir(&format!("{:?} {:?}", conversor, pixel), || {
+                let f: frame::Video = convertidor.convertir(0, black_box(&img)).unwrap();
+                black_box(f);
+            });
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::str::FromStr;
+use image::RgbImage;
+
+use crate::error;
+use crate::filtros::{self, Contexto, FrameFilter};
+use crate::informe;
+
+// Fracción de pixeles más oscuros y más claros que no cuentan para el
+// brillo, así un reflejo o una sombra no mueven la medición
+const DESCARTE: f64 = 0.01;
+// Límite de la corrección, como factor sobre el brillo medido
+const CORRECCION_MAXIMA: f64 = 2.0;
+// La pendiente se estima en una historia de este múltiplo de la ventana,
+// así el ruido del último frame casi no la mueve
+const HISTORIA_PENDIENTE: usize = 3;
+
+// Cómo se lleva cada frame al brillo objetivo
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Correccion {
+    // Multiplica todos los valores
+    Ganancia,
+    // Curva de gamma: mueve los medios tonos sin tocar el negro ni el blanco
+    Gamma,
+}
+
+// Suaviza el parpadeo por cambios de luz y exposición automática. Mide el
+// brillo de cada frame en su histograma de luminancia y lo lleva a una
+// tendencia suavizada. Los frames se procesan en orden y no se ve el futuro,
+// así que la tendencia es el promedio de la ventana de frames anteriores
+// corrido según la pendiente del brillo: sigue los cambios graduales de luz
+// sin atrasarse.
+pub struct Antiparpadeo {
+    ventana: usize,
+    // 0 no corrige nada, 1 lleva cada frame justo a la tendencia
+    fuerza: f64,
+    correccion: Correccion,
+    // CSV con el brillo medido, el objetivo y la corrección de cada frame
+    curva: Option<PathBuf>,
+    escritor: Option<informe::Escritor>,
+    // Índice y brillo de los últimos frames, HISTORIA_PENDIENTE ventanas
+    historia: VecDeque<(f64, f64)>,
+}
+
+impl Antiparpadeo {
+    pub fn new(ventana: usize, fuerza: f64, correccion: Correccion, curva: Option<PathBuf>) -> Self {
+        Antiparpadeo {
+            ventana: ventana.max(1),
+            fuerza: fuerza.clamp(0.0, 1.0),
+            correccion,
+            curva,
+            escritor: None,
+            historia: VecDeque::new(),
+        }
+    }
+
+    // Brillo que le corresponde al frame según la tendencia
+    fn objetivo(&self, indice: f64) -> f64 {
+        let medias = |puntos: &[(f64, f64)]| {
+            let n = puntos.len() as f64;
+            (
+                puntos.iter().map(|p| p.0).sum::<f64>() / n,
+                puntos.iter().map(|p| p.1).sum::<f64>() / n,
+            )
+        };
+        let historia = self.historia.as_slices();
+        let historia = [historia.0, historia.1].concat();
+
+        // Pendiente por mínimos cuadrados sobre toda la historia
+        let (media_x, media_y) = medias(&historia);
+        let covarianza: f64 = historia.iter().map(|p| (p.0 - media_x) * (p.1 - media_y)).sum();
+        let varianza: f64 = historia.iter().map(|p| (p.0 - media_x).powi(2)).sum();
+        let pendiente = if varianza > 0.0 { covarianza / varianza } else { 0.0 };
+
+        let (media_x, media_y) = medias(&historia[historia.len().saturating_sub(self.ventana)..]);
+        media_y + pendiente * (indice - media_x)
+    }
+
+    // Tabla de 8 bits que lleva `brillo` a `objetivo`, y el valor de la
+    // corrección (ganancia o exponente) para la curva
+    fn tabla(&self, brillo: f64, objetivo: f64) -> ([u8; 256], f64) {
+        match self.correccion {
+            Correccion::Ganancia => {
+                let ganancia = (objetivo / brillo)
+                    .clamp(1.0 / CORRECCION_MAXIMA, CORRECCION_MAXIMA)
+                    .powf(self.fuerza);
+                (filtros::tabla_ganancia(ganancia), ganancia)
+            }
+            Correccion::Gamma => {
+                // (brillo / 255) ^ gamma = objetivo / 255
+                let gamma = ((objetivo / 255.0).ln() / (brillo / 255.0).ln())
+                    .clamp(1.0 / CORRECCION_MAXIMA, CORRECCION_MAXIMA)
+                    .powf(self.fuerza);
+                let tabla = std::array::from_fn(|i| (255.0 * (i as f64 / 255.0).powf(gamma)).round() as u8);
+                (tabla, gamma)
+            }
+        }
+    }
+}
+
+// Parámetros separados por comas: "ventana=15" (frames), "fuerza=0.8",
+// "gamma" o "ganancia" (por defecto) y "curva=archivo.csv"
+impl FromStr for Antiparpadeo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ventana = 15;
+        let mut fuerza = 1.0;
+        let mut correccion = Correccion::Ganancia;
+        let mut curva = None;
+        for parametro in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match parametro.split_once('=') {
+                None if parametro == "ganancia" => correccion = Correccion::Ganancia,
+                None if parametro == "gamma" => correccion = Correccion::Gamma,
+                Some(("ventana", valor)) => {
+                    ventana = valor
+                        .trim()
+                        .parse()
+                        .ok()
+                        .filter(|&v| v > 0)
+                        .ok_or_else(|| format!("ventana inválida: '{}'", valor))?;
+                }
+                Some(("fuerza", valor)) => fuerza = filtros::parse_fuerza(valor)?,
+                Some(("curva", ruta)) => curva = Some(PathBuf::from(ruta.trim())),
+                _ => return Err(format!("parámetro desconocido: '{}'", parametro)),
+            }
+        }
+        Ok(Antiparpadeo::new(ventana, fuerza, correccion, curva))
+    }
+}
+
+impl FrameFilter for Antiparpadeo {
+    fn nombre(&self) -> &str {
+        "antiparpadeo"
+    }
+
+    fn reiniciar(&mut self) -> error::Result<()> {
+        self.historia.clear();
+        let cabecera = ["frame", "archivo", "brillo", "objetivo", "correccion"];
+        informe::reabrir_csv(&mut self.escritor, self.curva.as_deref(), &cabecera)
+    }
+
+    fn aplicar(&mut self, mut img: RgbImage, ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        let indice = ctx.indice as f64;
+        let brillo = brillo(&img);
+        if self.historia.len() == self.ventana * HISTORIA_PENDIENTE {
+            self.historia.pop_front();
+        }
+        self.historia.push_back((indice, brillo));
+        let objetivo = self.objetivo(indice).clamp(1.0, 254.0);
+
+        // Un frame casi negro o casi blanco no se puede corregir con una curva
+        let (tabla, valor) = match (1.0..=254.0).contains(&brillo) {
+            true => self.tabla(brillo, objetivo),
+            false => (std::array::from_fn(|i| i as u8), 1.0),
+        };
+        if valor != 1.0 {
+            for canal in img.iter_mut() {
+                *canal = tabla[*canal as usize];
+            }
+        }
+
+        if let Some(escritor) = self.escritor.as_mut() {
+            escritor
+                .write_record([
+                    (ctx.indice + 1).to_string(),
+                    ctx.frame.path.display().to_string(),
+                    format!("{:.2}", brillo),
+                    format!("{:.2}", objetivo),
+                    format!("{:.4}", valor),
+                ])
+                .and_then(|_| escritor.flush().map_err(csv::Error::from))
+                .map_err(|e| ctx.error("antiparpadeo", e.to_string()))?;
+        }
+        Ok(img)
+    }
+}
+
+// Media de la luminancia (BT.709) sin los extremos del histograma. Se mide
+// uno de cada cuatro pixeles, alcanza para el brillo general.
+fn brillo(img: &RgbImage) -> f64 {
+    let mut histograma = [0u64; 256];
+    for pixel in img.pixels().step_by(4) {
+        let [r, g, b] = pixel.0;
+        let luma = (54 * r as u32 + 183 * g as u32 + 19 * b as u32) >> 8;
+        histograma[luma as usize] += 1;
+    }
+    let total: u64 = histograma.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    // Se recorren los valores quitando DESCARTE de cada punta
+    let descarte = (total as f64 * DESCARTE) as u64;
+    let (mut saltear, mut quedan) = (descarte, total - 2 * descarte);
+    let (mut suma, mut contados) = (0.0, 0);
+    for (valor, &cantidad) in histograma.iter().enumerate() {
+        let salteados = cantidad.min(saltear);
+        saltear -= salteados;
+        let usados = (cantidad - salteados).min(quedan);
+        quedan -= usados;
+        suma += valor as f64 * usados as f64;
+        contados += usados;
+    }
+    if contados == 0 { 0.0 } else { suma / contados as f64 }
+}
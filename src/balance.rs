@@ -0,0 +1,187 @@
+use std::str::FromStr;
+use image::{Rgb, RgbImage};
+
+use crate::error;
+use crate::filtros::{self, Contexto, FrameFilter};
+use crate::frames::Recorte;
+
+// Límite de la ganancia de cada canal
+const GANANCIA_MAXIMA: f64 = 2.0;
+// Pixeles que no cuentan para estimar el color: los quemados, que ya
+// perdieron el tono, y los muy oscuros, que son mayormente ruido
+const SATURADO: u8 = 250;
+const OSCURO: u32 = 10;
+
+// A qué se lleva el color de cada frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Objetivo {
+    // El tono que tiene el primer frame
+    Referencia,
+    // Lo neutro queda del color de esta temperatura, en kelvin
+    Temperatura(f64),
+}
+
+// Mantiene el balance de blancos parejo entre frames, p. ej. cuando se
+// prenden y apagan lámparas entre sesiones. Estima el tono de cada frame
+// con el promedio de la imagen (mundo gris) o de una zona que se sabe
+// neutra, como una parte blanca de la base, y ajusta la ganancia de cada
+// canal sin cambiar el brillo.
+#[derive(Clone)]
+pub struct Balance {
+    objetivo: Objetivo,
+    // Zona neutra en coordenadas de la imagen que llega al filtro
+    zona: Option<Recorte>,
+    // 0 no corrige nada, 1 corrige del todo
+    fuerza: f64,
+    // Tono buscado como proporciones R/G y B/G; con Objetivo::Referencia se
+    // toma del primer frame
+    tono: Option<(f64, f64)>,
+}
+
+impl Balance {
+    pub fn new(objetivo: Objetivo, zona: Option<Recorte>, fuerza: f64) -> Self {
+        let tono = match objetivo {
+            Objetivo::Referencia => None,
+            Objetivo::Temperatura(kelvin) => {
+                // Relativo a D65, que es el blanco de sRGB
+                let [r, g, b] = color_temperatura(kelvin);
+                let [r65, g65, b65] = color_temperatura(6500.0);
+                Some(((r / r65) / (g / g65), (b / b65) / (g / g65)))
+            }
+        };
+        Balance {
+            objetivo,
+            zona,
+            fuerza: fuerza.clamp(0.0, 1.0),
+            tono,
+        }
+    }
+}
+
+// Parámetros separados por comas: "referencia" (por defecto) o
+// "temperatura=5500", "zona=x:y:ancho:alto" y "fuerza=0.8"
+impl FromStr for Balance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut objetivo = Objetivo::Referencia;
+        let mut zona = None;
+        let mut fuerza = 1.0;
+        for parametro in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match parametro.split_once('=') {
+                None if parametro == "referencia" => objetivo = Objetivo::Referencia,
+                Some(("temperatura", valor)) => {
+                    let kelvin = valor
+                        .trim()
+                        .trim_end_matches(['k', 'K'])
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|k| (1000.0..=40000.0).contains(k))
+                        .ok_or_else(|| format!("la temperatura va de 1000 a 40000 K: '{}'", valor))?;
+                    objetivo = Objetivo::Temperatura(kelvin);
+                }
+                Some(("zona", valor)) => {
+                    let recorte = valor.replace(':', ",").parse::<Recorte>();
+                    zona = Some(recorte.map_err(|_| format!("zona inválida, se esperaba x:y:ancho:alto: '{}'", valor))?);
+                }
+                Some(("fuerza", valor)) => fuerza = filtros::parse_fuerza(valor)?,
+                _ => return Err(format!("parámetro desconocido: '{}'", parametro)),
+            }
+        }
+        Ok(Balance::new(objetivo, zona, fuerza))
+    }
+}
+
+impl FrameFilter for Balance {
+    fn nombre(&self) -> &str {
+        "balance"
+    }
+
+    // Con una temperatura fija cada frame se corrige por separado
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        match self.objetivo {
+            Objetivo::Temperatura(_) => Some(Box::new(self.clone())),
+            Objetivo::Referencia => None,
+        }
+    }
+
+    fn reiniciar(&mut self) -> error::Result<()> {
+        if self.objetivo == Objetivo::Referencia {
+            self.tono = None;
+        }
+        Ok(())
+    }
+
+    fn aplicar(&mut self, mut img: RgbImage, _ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        // Un frame sin pixeles útiles queda como está
+        let Some([r, g, b]) = color_promedio(&img, self.zona) else {
+            return Ok(img);
+        };
+        let (rojo, azul) = *self.tono.get_or_insert((r / g, b / g));
+
+        // Lo neutro pasa a tener el tono buscado y el mismo brillo
+        let deseado = [rojo * g, g, azul * g];
+        let brillo = |[r, g, b]: [f64; 3]| 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let escala = brillo([r, g, b]) / brillo(deseado);
+        let ganancias = [deseado[0] * escala / r, deseado[1] * escala / g, deseado[2] * escala / b]
+            .map(|ganancia| ganancia.clamp(1.0 / GANANCIA_MAXIMA, GANANCIA_MAXIMA).powf(self.fuerza));
+
+        let tablas = ganancias.map(filtros::tabla_ganancia);
+        for Rgb(pixel) in img.pixels_mut() {
+            for (canal, tabla) in pixel.iter_mut().zip(&tablas) {
+                *canal = tabla[*canal as usize];
+            }
+        }
+        Ok(img)
+    }
+}
+
+// Promedio de cada canal en la zona (o en toda la imagen), salteando los
+// pixeles quemados y los muy oscuros. Se mide uno de cada cuatro pixeles.
+fn color_promedio(img: &RgbImage, zona: Option<Recorte>) -> Option<[f64; 3]> {
+    let (w, h) = img.dimensions();
+    let zona = zona.unwrap_or(Recorte { x: 0, y: 0, ancho: w, alto: h });
+    let x0 = zona.x.min(w);
+    let y0 = zona.y.min(h);
+    let x1 = (zona.x.saturating_add(zona.ancho)).min(w);
+    let y1 = (zona.y.saturating_add(zona.alto)).min(h);
+
+    let mut suma = [0u64; 3];
+    let mut contados = 0u64;
+    for y in (y0..y1).step_by(2) {
+        for x in (x0..x1).step_by(2) {
+            let Rgb(pixel) = *img.get_pixel(x, y);
+            if pixel.iter().any(|&c| c >= SATURADO) || pixel.iter().map(|&c| c as u32).sum::<u32>() < OSCURO * 3 {
+                continue;
+            }
+            for (total, canal) in suma.iter_mut().zip(pixel) {
+                *total += canal as u64;
+            }
+            contados += 1;
+        }
+    }
+    if contados == 0 || suma.contains(&0) {
+        return None;
+    }
+    Some(suma.map(|total| total as f64 / contados as f64))
+}
+
+// Color aproximado de un cuerpo negro a esa temperatura, en 0-255
+// (aproximación de Tanner Helland)
+fn color_temperatura(kelvin: f64) -> [f64; 3] {
+    let t = kelvin / 100.0;
+    let r = if t <= 66.0 { 255.0 } else { 329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2) };
+    let g = if t <= 66.0 {
+        99.470_802_586_1 * t.ln() - 161.119_568_166_1
+    } else {
+        288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7
+    };
+    [r, g, b].map(|c| c.clamp(1.0, 255.0))
+}
@@ -0,0 +1,330 @@
+use std::path::Path;
+use clap::ValueEnum;
+use ffmpeg_next::codec::{encoder, Id};
+use ffmpeg_next::format::Pixel;
+
+use crate::error::{self, Error};
+use crate::frames;
+
+// Códec de video de la salida
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Codec {
+    #[default]
+    H264,
+    H265,
+    Vp9,
+    Av1,
+    // Para archivar masters: 4:2:2 de 10 bits, intra
+    Prores,
+    Mjpeg,
+}
+
+// Contenedores que se reconocen por la extensión de la salida
+const CONTENEDORES: &[&str] = &["mp4", "m4v", "mov", "mkv", "webm", "avi"];
+// Encoders que saben codificar en dos pasadas (ver `dos_pasadas`)
+const DOS_PASADAS: &[&str] = &["libx264", "libx265", "libvpx-vp9", "libaom-av1"];
+
+impl Codec {
+    pub fn id(self) -> Id {
+        match self {
+            Codec::H264 => Id::H264,
+            Codec::H265 => Id::HEVC,
+            Codec::Vp9 => Id::VP9,
+            Codec::Av1 => Id::AV1,
+            Codec::Prores => Id::PRORES,
+            Codec::Mjpeg => Id::MJPEG,
+        }
+    }
+
+    // Encoders de ffmpeg en orden de preferencia
+    fn encoders(self) -> &'static [&'static str] {
+        match self {
+            Codec::H264 => &["libx264"],
+            Codec::H265 => &["libx265"],
+            Codec::Vp9 => &["libvpx-vp9"],
+            Codec::Av1 => &["libsvtav1", "libaom-av1"],
+            Codec::Prores => &["prores_ks"],
+            Codec::Mjpeg => &["mjpeg"],
+        }
+    }
+
+    // Formato de pixel por defecto con el que se alimenta al encoder
+    pub fn pixel(self) -> Pixel {
+        match self {
+            Codec::Prores => Pixel::YUV422P10LE,
+            // El encoder MJPEG espera el rango completo
+            Codec::Mjpeg => Pixel::YUVJ420P,
+            Codec::H264 | Codec::H265 | Codec::Vp9 | Codec::Av1 => Pixel::YUV420P,
+        }
+    }
+
+    fn contenedores(self) -> &'static [&'static str] {
+        match self {
+            Codec::H264 | Codec::H265 => &["mp4", "m4v", "mov", "mkv"],
+            Codec::Vp9 => &["webm", "mkv", "mp4"],
+            Codec::Av1 => &["mp4", "mkv", "webm"],
+            Codec::Prores => &["mov", "mkv"],
+            Codec::Mjpeg => &["avi", "mov", "mkv"],
+        }
+    }
+
+    // Verifica que el contenedor de la salida admita el códec. Las
+    // extensiones que no se conocen quedan a criterio de ffmpeg.
+    pub fn validar_contenedor(self, salida: &Path) -> error::Result<()> {
+        let extension = salida
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if CONTENEDORES.contains(&extension.as_str()) && !self.contenedores().contains(&extension.as_str()) {
+            return Err(Error::Configuracion(format!(
+                "el códec {:?} no se puede guardar en .{} (usar {})",
+                self,
+                extension,
+                self.contenedores().join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    // Busca el primer encoder disponible en esta instalación de ffmpeg y
+    // verifica que acepte el formato de pixel. Con `dos_pasadas` solo sirven
+    // los que saben codificar en dos pasadas; en AV1 se usa libaom en lugar
+    // de SVT-AV1.
+    pub fn encoder(self, pixel: Pixel, dos_pasadas: bool) -> error::Result<ffmpeg_next::Codec> {
+        let admitido = |encoder: &ffmpeg_next::Codec| !dos_pasadas || DOS_PASADAS.contains(&encoder.name());
+        let candidatos: Vec<_> = self
+            .encoders()
+            .iter()
+            .copied()
+            .filter(|nombre| !dos_pasadas || DOS_PASADAS.contains(nombre))
+            .collect();
+        let encoder = candidatos
+            .iter()
+            .find_map(|nombre| encoder::find_by_name(nombre))
+            .or_else(|| encoder::find(self.id()).filter(admitido))
+            .ok_or_else(|| match (dos_pasadas, candidatos.is_empty()) {
+                (true, true) => Error::Configuracion(format!(
+                    "el códec {:?} no admite codificación en dos pasadas (--tamano-objetivo)",
+                    self
+                )),
+                (true, false) => Error::Configuracion(format!(
+                    "ffmpeg no tiene un encoder para {:?} en dos pasadas (se buscó {})",
+                    self,
+                    candidatos.join(", ")
+                )),
+                (false, _) => Error::Configuracion(format!(
+                    "ffmpeg no tiene un encoder para {:?} (se buscó {})",
+                    self,
+                    self.encoders().join(", ")
+                )),
+            })?;
+
+        let formatos = encoder.video().ok().and_then(|v| v.formats());
+        if let Some(mut formatos) = formatos
+            && !formatos.any(|f| f == pixel)
+        {
+            return Err(Error::Configuracion(format!(
+                "el encoder {} no acepta el formato {:?}",
+                encoder.name(),
+                pixel
+            )));
+        }
+        Ok(encoder)
+    }
+}
+
+// Ajustes de x264/x265 para el tipo de contenido
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tune {
+    Film,
+    Animation,
+    Grain,
+    // Para fotos fijas, como un timelapse con pocos cambios entre frames
+    Stillimage,
+    Fastdecode,
+    Zerolatency,
+    Psnr,
+    Ssim,
+}
+
+impl Tune {
+    fn nombre(self) -> &'static str {
+        match self {
+            Tune::Film => "film",
+            Tune::Animation => "animation",
+            Tune::Grain => "grain",
+            Tune::Stillimage => "stillimage",
+            Tune::Fastdecode => "fastdecode",
+            Tune::Zerolatency => "zerolatency",
+            Tune::Psnr => "psnr",
+            Tune::Ssim => "ssim",
+        }
+    }
+}
+
+// Calidad y control de tasa. Lo que no se indica queda con el valor por
+// defecto del encoder.
+#[derive(Clone, Debug, Default)]
+pub struct Calidad {
+    // Calidad constante: más bajo es mejor
+    pub crf: Option<u32>,
+    // Tasa de bits en bits por segundo
+    pub bitrate: Option<u64>,
+    pub maxrate: Option<u64>,
+    pub bufsize: Option<u64>,
+    pub preset: Option<String>,
+    pub tune: Option<Tune>,
+    pub perfil: Option<String>,
+    pub nivel: Option<String>,
+    // Distancia máxima entre keyframes, en frames
+    pub gop: Option<u32>,
+}
+
+impl Calidad {
+    // Traduce los ajustes a las opciones del encoder elegido
+    pub fn opciones(&self, encoder: &str) -> error::Result<Vec<(String, String)>> {
+        let no_admite = |opcion: &str| {
+            Error::Configuracion(format!("el encoder {} no admite {}", encoder, opcion))
+        };
+        let mut opciones = Vec::new();
+        let mut agregar = |clave: &str, valor: String| opciones.push((clave.to_string(), valor));
+
+        if self.crf.is_some() && self.bitrate.is_some() {
+            return Err(Error::Configuracion(
+                "--crf y --bitrate no se pueden usar juntos".to_string(),
+            ));
+        }
+        if self.maxrate.is_some() && self.bufsize.is_none() {
+            return Err(Error::Configuracion("--maxrate requiere --bufsize".to_string()));
+        }
+
+        if let Some(crf) = self.crf {
+            let maximo = match encoder {
+                "libx264" | "libx265" => 51,
+                "libvpx-vp9" | "libaom-av1" | "libsvtav1" => 63,
+                _ => return Err(no_admite("--crf")),
+            };
+            if crf > maximo {
+                return Err(Error::Configuracion(format!(
+                    "--crf para {} va de 0 a {}",
+                    encoder, maximo
+                )));
+            }
+            agregar("crf", crf.to_string());
+            // libvpx y libaom solo usan calidad constante con la tasa en cero
+            if matches!(encoder, "libvpx-vp9" | "libaom-av1") {
+                agregar("b", "0".to_string());
+            }
+        }
+        if let Some(bitrate) = self.bitrate {
+            agregar("b", bitrate.to_string());
+        }
+        if let Some(maxrate) = self.maxrate {
+            agregar("maxrate", maxrate.to_string());
+        }
+        if let Some(bufsize) = self.bufsize {
+            agregar("bufsize", bufsize.to_string());
+        }
+
+        if let Some(preset) = &self.preset {
+            match encoder {
+                "libx264" | "libx265" | "libsvtav1" => agregar("preset", preset.clone()),
+                // En libvpx y libaom la velocidad se indica con cpu-used
+                "libvpx-vp9" | "libaom-av1" => agregar("cpu-used", preset.clone()),
+                _ => return Err(no_admite("--preset")),
+            }
+        }
+        if let Some(tune) = self.tune {
+            let admitido = match encoder {
+                "libx264" => true,
+                "libx265" => !matches!(tune, Tune::Film | Tune::Stillimage),
+                _ => false,
+            };
+            if !admitido {
+                return Err(no_admite(&format!("--tune {}", tune.nombre())));
+            }
+            agregar("tune", tune.nombre().to_string());
+        }
+
+        if let Some(perfil) = &self.perfil {
+            agregar("profile", perfil.clone());
+        }
+        if let Some(nivel) = &self.nivel {
+            agregar("level", nivel.clone());
+        }
+        if let Some(gop) = self.gop {
+            agregar("g", gop.to_string());
+        }
+        Ok(opciones)
+    }
+}
+
+// Tasa de bits con sufijo opcional: "800k", "5M", "2500000"
+pub fn parse_bitrate(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (numero, factor) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1_000.0),
+        Some((i, 'm' | 'M')) => (&s[..i], 1_000_000.0),
+        _ => (s, 1.0),
+    };
+    let valor: f64 = numero
+        .parse()
+        .map_err(|_| format!("tasa de bits inválida: '{}'", s))?;
+    if !valor.is_finite() || valor <= 0.0 {
+        return Err(format!("la tasa de bits debe ser positiva: '{}'", s));
+    }
+    Ok((valor * factor) as u64)
+}
+
+// Cómo guarda cada encoder las estadísticas de la primera pasada
+pub enum Estadisticas {
+    // El encoder escribe y lee el archivo indicado en las opciones
+    Archivo(Vec<(String, String)>),
+    // Se pasan por stats_out/stats_in del contexto y las guardamos nosotros
+    Contexto,
+}
+
+// Opciones de una pasada (1 o 2) de la codificación en dos pasadas
+pub fn dos_pasadas(encoder: &str, pasada: u32, archivo: &Path) -> error::Result<Estadisticas> {
+    let archivo = archivo.to_string_lossy();
+    match encoder {
+        "libx264" => Ok(Estadisticas::Archivo(vec![("stats".to_string(), archivo.into_owned())])),
+        "libx265" => Ok(Estadisticas::Archivo(vec![(
+            "x265-params".to_string(),
+            format!("pass={}:stats={}", pasada, archivo),
+        )])),
+        "libvpx-vp9" | "libaom-av1" => Ok(Estadisticas::Contexto),
+        _ => Err(Error::Configuracion(format!(
+            "el encoder {} no admite codificación en dos pasadas",
+            encoder
+        ))),
+    }
+}
+
+// Tasa de bits para que el video pese `bytes`; se deja un 2% para la
+// cabecera y los índices del contenedor
+pub fn bitrate_para_tamano(bytes: u64, duracion_ms: i64) -> error::Result<u64> {
+    if duracion_ms <= 0 {
+        return Err(Error::Configuracion("el video no tiene duración".to_string()));
+    }
+    let bitrate = (bytes as f64 * 8.0 * 0.98 * 1_000.0 / duracion_ms as f64) as u64;
+    if bitrate < 10_000 {
+        return Err(Error::Configuracion(format!(
+            "el tamaño objetivo es demasiado chico para {} de video",
+            frames::formatear_duracion(duracion_ms)
+        )));
+    }
+    Ok(bitrate)
+}
+
+// Tamaño en MB (1 MB = 1.000.000 bytes), devuelto en bytes
+pub fn parse_megabytes(s: &str) -> Result<u64, String> {
+    let valor: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("tamaño inválido: '{}'", s))?;
+    if !valor.is_finite() || valor <= 0.0 {
+        return Err(format!("el tamaño debe ser positivo: '{}'", s));
+    }
+    Ok((valor * 1_000_000.0) as u64)
+}
@@ -0,0 +1,491 @@
+use std::os::raw::c_int;
+use std::slice;
+use clap::ValueEnum;
+use ffmpeg_next::color::{Primaries, Range, Space, TransferCharacteristic};
+use ffmpeg_next::codec::encoder;
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::frame;
+use ffmpeg_next::software::scaling::{self, ColorSpace};
+use rayon::prelude::*;
+
+use crate::error::{self, Error, Etapa};
+
+// Cómo se pasa de RGB al formato de pixel del encoder
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Conversor {
+    // libswscale: admite cualquier formato de pixel y escala en el mismo paso
+    #[default]
+    Swscale,
+    // Conversión propia, solo para yuv420p, yuvj420p y yuv422p10le
+    Nativo,
+}
+
+impl Conversor {
+    // Verifica que el conversor sepa llenar el formato de pixel
+    pub fn validar(self, pixel: Pixel) -> error::Result<()> {
+        if self == Conversor::Nativo && !nativo_admite(pixel) {
+            return Err(Error::Configuracion(format!(
+                "el conversor nativo no admite el formato {:?}; usar --conversor swscale",
+                pixel
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Formatos que sabe llenar el conversor nativo
+pub fn nativo_admite(pixel: Pixel) -> bool {
+    matches!(pixel, Pixel::YUV420P | Pixel::YUVJ420P | Pixel::YUV422P10LE)
+}
+
+// Formatos de pixel por nombre de ffmpeg, p. ej. yuv444p o yuv420p10le
+pub fn parse_pixel(s: &str) -> Result<Pixel, String> {
+    s.parse().map_err(|_| format!("formato de pixel desconocido: '{}'", s))
+}
+
+// Matriz para pasar de RGB a YCbCr
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Matriz {
+    Bt709,
+    Bt601,
+}
+
+// Rango de valores de las muestras
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rango {
+    // 16-235 para luma y 16-240 para croma (en 8 bits), el de los videos
+    #[default]
+    Limitado,
+    // 0-255, el que espera MJPEG
+    Completo,
+}
+
+// Espacio de color de la salida
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color {
+    pub matriz: Matriz,
+    pub rango: Rango,
+}
+
+impl Color {
+    // Sin matriz explícita se usa la que asumen los reproductores cuando el
+    // video no trae etiquetas: BT.709 desde 720 líneas, BT.601 por debajo
+    pub fn para(height: u32, matriz: Option<Matriz>, rango: Rango) -> Color {
+        let matriz = matriz.unwrap_or(if height >= 720 { Matriz::Bt709 } else { Matriz::Bt601 });
+        Color { matriz, rango }
+    }
+
+    fn espacio_swscale(self) -> ColorSpace {
+        match self.matriz {
+            Matriz::Bt709 => ColorSpace::ITU709,
+            Matriz::Bt601 => ColorSpace::ITU601,
+        }
+    }
+
+    fn espacio(self) -> Space {
+        match self.matriz {
+            Matriz::Bt709 => Space::BT709,
+            Matriz::Bt601 => Space::SMPTE170M,
+        }
+    }
+
+    fn primarios(self) -> Primaries {
+        match self.matriz {
+            Matriz::Bt709 => Primaries::BT709,
+            Matriz::Bt601 => Primaries::SMPTE170M,
+        }
+    }
+
+    fn transferencia(self) -> TransferCharacteristic {
+        match self.matriz {
+            Matriz::Bt709 => TransferCharacteristic::BT709,
+            Matriz::Bt601 => TransferCharacteristic::SMPTE170M,
+        }
+    }
+
+    fn rango_ffmpeg(self) -> Range {
+        match self.rango {
+            Rango::Limitado => Range::MPEG,
+            Rango::Completo => Range::JPEG,
+        }
+    }
+
+    // Etiquetas del stream para que los reproductores usen la misma matriz
+    pub fn etiquetar_encoder(self, encoder: &mut encoder::Video) {
+        encoder.set_colorspace(self.espacio());
+        encoder.set_color_range(self.rango_ffmpeg());
+        // ffmpeg-next no tiene setters para los primarios y la transferencia
+        unsafe {
+            let contexto = encoder.as_mut_ptr();
+            (*contexto).color_primaries = self.primarios().into();
+            (*contexto).color_trc = self.transferencia().into();
+        }
+    }
+
+    pub fn etiquetar_frame(self, frame: &mut frame::Video) {
+        frame.set_color_space(self.espacio());
+        frame.set_color_range(self.rango_ffmpeg());
+        frame.set_color_primaries(self.primarios());
+        frame.set_color_transfer_characteristic(self.transferencia());
+    }
+
+    fn coeficientes(self, bits: u32) -> Coeficientes {
+        let (kr, kb): (f64, f64) = match self.matriz {
+            Matriz::Bt709 => (0.2126, 0.0722),
+            Matriz::Bt601 => (0.299, 0.114),
+        };
+        let kg = 1.0 - kr - kb;
+        let maximo = (1 << bits) - 1;
+        let escala = 1 << (bits - 8);
+        let (luma, croma, base) = match self.rango {
+            Rango::Limitado => (219.0 * escala as f64, 224.0 * escala as f64, 16 * escala),
+            Rango::Completo => (maximo as f64, maximo as f64, 0),
+        };
+        // Factor por componente de 8 bits, en punto fijo
+        let fijo = |x: f64| (x / 255.0 * (1 << FRACCION) as f64).round() as i32;
+        let (du, dv) = (2.0 * (1.0 - kb), 2.0 * (1.0 - kr));
+        let medio = 1 << (FRACCION - 1);
+        Coeficientes {
+            y: [fijo(luma * kr), fijo(luma * kg), fijo(luma * kb)],
+            u: [fijo(-croma * kr / du), fijo(-croma * kg / du), fijo(croma / 2.0)],
+            v: [fijo(croma / 2.0), fijo(-croma * kg / dv), fijo(-croma * kb / dv)],
+            base: (base << FRACCION) + medio,
+            centro: ((128 * escala) << FRACCION) + medio,
+            maximo,
+        }
+    }
+}
+
+// Bits de fracción de los coeficientes en punto fijo
+const FRACCION: u32 = 16;
+
+// Coeficientes de una matriz para RGB de 8 bits, en punto fijo y en la
+// escala de la profundidad de salida. `base` y `centro` ya incluyen el
+// redondeo.
+#[derive(Clone, Copy)]
+struct Coeficientes {
+    y: [i32; 3],
+    u: [i32; 3],
+    v: [i32; 3],
+    base: i32,
+    centro: i32,
+    maximo: i32,
+}
+
+impl Coeficientes {
+    #[inline(always)]
+    fn luma(&self, r: i32, g: i32, b: i32) -> i32 {
+        ((self.y[0] * r + self.y[1] * g + self.y[2] * b + self.base) >> FRACCION).clamp(0, self.maximo)
+    }
+
+    // Croma de la suma de 2^n pixels: el desplazamiento hace el promedio
+    #[inline(always)]
+    fn croma(&self, [r, g, b]: [i32; 3], n: u32) -> (i32, i32) {
+        let u = ((self.u[0] * r + self.u[1] * g + self.u[2] * b) >> n) + self.centro;
+        let v = ((self.v[0] * r + self.v[1] * g + self.v[2] * b) >> n) + self.centro;
+        (
+            (u >> FRACCION).clamp(0, self.maximo),
+            (v >> FRACCION).clamp(0, self.maximo),
+        )
+    }
+}
+
+// Convierte las imágenes RGB al formato y tamaño que recibe el encoder
+pub struct Convertidor {
+    conversor: Conversor,
+    pixel: Pixel,
+    width: u32,
+    height: u32,
+    color: Color,
+    escalador: Option<Escalador>,
+}
+
+// Contexto de swscale para un tamaño de entrada, con el frame RGB que se
+// reutiliza entre imágenes
+struct Escalador {
+    contexto: scaling::Context,
+    entrada: frame::Video,
+}
+
+impl Convertidor {
+    pub fn new(conversor: Conversor, pixel: Pixel, width: u32, height: u32, color: Color) -> error::Result<Self> {
+        conversor.validar(pixel)?;
+        Ok(Convertidor {
+            conversor,
+            pixel,
+            width,
+            height,
+            color,
+            escalador: None,
+        })
+    }
+
+    pub fn dimensiones(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    // Con swscale las imágenes de otro tamaño se escalan al convertir
+    pub fn escala(&self) -> bool {
+        self.conversor == Conversor::Swscale
+    }
+
+    pub fn convertir(&mut self, indice: usize, img: &image::RgbImage) -> error::Result<frame::Video> {
+        let mut f = frame::Video::new(self.pixel, self.width, self.height);
+        match self.conversor {
+            Conversor::Nativo => rgb_a_frame(img, &mut f, self.color),
+            Conversor::Swscale => {
+                let escalador = self.escalador(img.dimensions())?;
+                copiar_rgb(img, &mut escalador.entrada);
+                escalador
+                    .contexto
+                    .run(&escalador.entrada, &mut f)
+                    .map_err(Error::ffmpeg_frame(indice, Etapa::Conversion))?;
+                self.color.etiquetar_frame(&mut f);
+            }
+        }
+        Ok(f)
+    }
+
+    // El contexto se rehace solo cuando cambia el tamaño de entrada
+    fn escalador(&mut self, (width, height): (u32, u32)) -> error::Result<&mut Escalador> {
+        let vigente = self
+            .escalador
+            .as_ref()
+            .is_some_and(|e| (e.entrada.width(), e.entrada.height()) == (width, height));
+        if !vigente {
+            let flags = scaling::Flags::LANCZOS | scaling::Flags::ACCURATE_RND | scaling::Flags::FULL_CHR_H_INP;
+            let mut contexto = scaling::Context::get(Pixel::RGB24, width, height, self.pixel, self.width, self.height, flags)
+                .map_err(Error::ffmpeg(Etapa::Conversion))?;
+            // La entrada es RGB de rango completo; la salida usa la matriz y el
+            // rango elegidos
+            unsafe {
+                let rgb = ffmpeg_next::ffi::sws_getCoefficients(c_int::from(ColorSpace::Default));
+                let tabla = ffmpeg_next::ffi::sws_getCoefficients(c_int::from(self.color.espacio_swscale()));
+                let rango = (self.color.rango == Rango::Completo) as c_int;
+                ffmpeg_next::ffi::sws_setColorspaceDetails(contexto.as_mut_ptr(), rgb, 1, tabla, rango, 0, 1 << 16, 1 << 16);
+            }
+            self.escalador = Some(Escalador {
+                contexto,
+                entrada: frame::Video::new(Pixel::RGB24, width, height),
+            });
+        }
+        Ok(self.escalador.as_mut().unwrap())
+    }
+}
+
+// Copia los pixels fila por fila, porque el frame puede tener relleno al final
+fn copiar_rgb(img: &image::RgbImage, frame: &mut frame::Video) {
+    let fila = img.width() as usize * 3;
+    let stride = frame.stride(0);
+    let datos = frame.data_mut(0);
+    for (y, origen) in img.as_raw().chunks_exact(fila).enumerate() {
+        datos[y * stride..y * stride + fila].copy_from_slice(origen);
+    }
+}
+
+// Llena el frame según su formato de pixel
+pub fn rgb_a_frame(rgb: &image::RgbImage, frame: &mut frame::Video, color: Color) {
+    let (width, height) = (frame.width(), frame.height());
+    match frame.format() {
+        Pixel::YUV422P10LE => rgb_to_yuv422p10(rgb, frame, width, height, color),
+        _ => rgb_to_yuv420p(rgb, frame, width, height, color),
+    }
+    color.etiquetar_frame(frame);
+}
+
+// 4:2:0 de 8 bits: cada muestra de croma es el promedio de un bloque de 2x2
+pub fn rgb_to_yuv420p(rgb: &image::RgbImage, frame: &mut frame::Video, width: u32, height: u32, color: Color) {
+    convertir_planar::<U8>(rgb, frame, width, height, color.coeficientes(8), 2);
+}
+
+// 4:2:2 de 10 bits para ProRes: croma promediada de a pares horizontales,
+// cada muestra en dos bytes little endian
+pub fn rgb_to_yuv422p10(rgb: &image::RgbImage, frame: &mut frame::Video, width: u32, height: u32, color: Color) {
+    convertir_planar::<U16Le>(rgb, frame, width, height, color.coeficientes(10), 1);
+}
+
+// Filas de croma que procesa cada tarea
+const FILAS_POR_TAREA: usize = 16;
+
+// Pixels por bloque en el camino rápido. Los bloques se recorren como
+// arrays de tamaño fijo, así el compilador no revisa los límites en cada
+// muestra y vectoriza el bucle sin depender de intrínsecos.
+const BLOQUE: usize = 8;
+
+// Escribe directo en los planos del frame, repartiendo bandas de filas entre
+// los hilos. `filas_croma` es cuántas filas de luma comparten una de croma.
+fn convertir_planar<M: Muestra>(
+    rgb: &image::RgbImage,
+    frame: &mut frame::Video,
+    width: u32,
+    height: u32,
+    c: Coeficientes,
+    filas_croma: usize,
+) {
+    let w = width as usize;
+    let h = height as usize;
+    let strides = [frame.stride(0), frame.stride(1), frame.stride(2)];
+    let [plano_y, plano_u, plano_v] = planos_mut(frame);
+    let origen = &rgb.as_raw()[..w * h * 3];
+
+    let filas_luma = FILAS_POR_TAREA * filas_croma;
+    plano_y
+        .par_chunks_mut(strides[0] * filas_luma)
+        .zip(plano_u.par_chunks_mut(strides[1] * FILAS_POR_TAREA))
+        .zip(plano_v.par_chunks_mut(strides[2] * FILAS_POR_TAREA))
+        .zip(origen.par_chunks(w * 3 * filas_luma))
+        .for_each(|(((banda_y, banda_u), banda_v), banda_rgb)| {
+            let filas: Vec<&[u8]> = banda_rgb.chunks(w * 3).collect();
+            for (i, fila) in filas.iter().enumerate() {
+                fila_luma::<M>(fila, &mut banda_y[i * strides[0]..], &c);
+            }
+            for (i, grupo) in filas.chunks(filas_croma).enumerate() {
+                let u = &mut banda_u[i * strides[1]..];
+                let v = &mut banda_v[i * strides[2]..];
+                fila_croma::<M>(grupo, u, v, &c);
+            }
+        });
+}
+
+// Luma de una fila, de a bloques y el resto pixel por pixel
+fn fila_luma<M: Muestra>(rgb: &[u8], salida: &mut [u8], c: &Coeficientes) {
+    let (bloques, resto) = rgb.as_chunks::<{ 3 * BLOQUE }>();
+    let (salida, salida_resto) = salida.split_at_mut(bloques.len() * BLOQUE * M::BYTES);
+    for (bloque, destino) in bloques.iter().zip(salida.chunks_exact_mut(BLOQUE * M::BYTES)) {
+        let pixels: &[[u8; 3]; BLOQUE] = bloque.as_chunks().0.try_into().unwrap();
+        let mut y = [0i32; BLOQUE];
+        for (valor, &[r, g, b]) in y.iter_mut().zip(pixels) {
+            *valor = c.luma(r as i32, g as i32, b as i32);
+        }
+        M::escribir_bloque(destino, y);
+    }
+    for (x, p) in resto.chunks_exact(3).enumerate() {
+        M::escribir(salida_resto, x, c.luma(p[0] as i32, p[1] as i32, p[2] as i32));
+    }
+}
+
+// Croma de una fila a partir de las filas de luma que la comparten (una o
+// dos). Con una sola fila se suma dos veces, que da el mismo promedio. En un
+// ancho impar la última muestra promedia solo la última columna.
+fn fila_croma<M: Muestra>(filas: &[&[u8]], u: &mut [u8], v: &mut [u8], c: &Coeficientes) {
+    let (arriba, abajo) = match filas {
+        [fila] => (*fila, *fila),
+        [arriba, abajo, ..] => (*arriba, *abajo),
+        [] => return,
+    };
+    let ancho = arriba.len() / 3;
+    let pares = ancho / 2;
+
+    // Cada bloque son BLOQUE pares de pixels de cada fila
+    let (bloques_arriba, _) = arriba[..pares * 6].as_chunks::<{ 6 * BLOQUE }>();
+    let (bloques_abajo, _) = abajo[..pares * 6].as_chunks::<{ 6 * BLOQUE }>();
+    let hechos = bloques_arriba.len() * BLOQUE;
+    let (u_bloques, u_resto) = u.split_at_mut(hechos * M::BYTES);
+    let (v_bloques, v_resto) = v.split_at_mut(hechos * M::BYTES);
+    let bloques = bloques_arriba
+        .iter()
+        .zip(bloques_abajo)
+        .zip(u_bloques.chunks_exact_mut(BLOQUE * M::BYTES))
+        .zip(v_bloques.chunks_exact_mut(BLOQUE * M::BYTES));
+    for (((arriba, abajo), destino_u), destino_v) in bloques {
+        let arriba: &[[u8; 6]; BLOQUE] = arriba.as_chunks().0.try_into().unwrap();
+        let abajo: &[[u8; 6]; BLOQUE] = abajo.as_chunks().0.try_into().unwrap();
+        let (mut mu, mut mv) = ([0i32; BLOQUE], [0i32; BLOQUE]);
+        for (((mu, mv), a), b) in mu.iter_mut().zip(&mut mv).zip(arriba).zip(abajo) {
+            let mut suma = [0i32; 3];
+            for (k, total) in suma.iter_mut().enumerate() {
+                *total = a[k] as i32 + a[k + 3] as i32 + b[k] as i32 + b[k + 3] as i32;
+            }
+            (*mu, *mv) = c.croma(suma, 2);
+        }
+        M::escribir_bloque(destino_u, mu);
+        M::escribir_bloque(destino_v, mv);
+    }
+
+    let filas = [arriba, abajo];
+    for x in hechos..pares {
+        let (mu, mv) = c.croma(sumar(&filas, x * 2, 2), 2);
+        M::escribir(u_resto, x - hechos, mu);
+        M::escribir(v_resto, x - hechos, mv);
+    }
+    if ancho % 2 == 1 {
+        let (mu, mv) = c.croma(sumar(&filas, ancho - 1, 1), 1);
+        M::escribir(u_resto, pares - hechos, mu);
+        M::escribir(v_resto, pares - hechos, mv);
+    }
+}
+
+// Suma de `cantidad` pixels desde la columna `x` en todas las filas
+#[inline(always)]
+fn sumar(filas: &[&[u8]], x: usize, cantidad: usize) -> [i32; 3] {
+    let mut suma = [0i32; 3];
+    for fila in filas {
+        for p in fila[x * 3..(x + cantidad) * 3].chunks_exact(3) {
+            suma[0] += p[0] as i32;
+            suma[1] += p[1] as i32;
+            suma[2] += p[2] as i32;
+        }
+    }
+    suma
+}
+
+// Los tres planos a la vez, para llenarlos en paralelo
+fn planos_mut(frame: &mut frame::Video) -> [&mut [u8]; 3] {
+    let mut plano = |i: usize| {
+        let datos = frame.data_mut(i);
+        // Cada plano es una zona distinta del buffer del frame, así que los
+        // tres slices no se solapan
+        unsafe { slice::from_raw_parts_mut(datos.as_mut_ptr(), datos.len()) }
+    };
+    [plano(0), plano(1), plano(2)]
+}
+
+// Tamaño de las muestras de un plano
+trait Muestra {
+    // Bytes por muestra
+    const BYTES: usize;
+
+    fn escribir(salida: &mut [u8], x: usize, valor: i32);
+
+    // `salida` mide justo BLOQUE * BYTES
+    fn escribir_bloque(salida: &mut [u8], valores: [i32; BLOQUE]);
+}
+
+struct U8;
+
+impl Muestra for U8 {
+    const BYTES: usize = 1;
+
+    #[inline(always)]
+    fn escribir(salida: &mut [u8], x: usize, valor: i32) {
+        salida[x] = valor as u8;
+    }
+
+    #[inline(always)]
+    fn escribir_bloque(salida: &mut [u8], valores: [i32; BLOQUE]) {
+        let salida: &mut [u8; BLOQUE] = salida.try_into().unwrap();
+        for (muestra, valor) in salida.iter_mut().zip(valores) {
+            *muestra = valor as u8;
+        }
+    }
+}
+
+// 9 a 16 bits, little endian
+struct U16Le;
+
+impl Muestra for U16Le {
+    const BYTES: usize = 2;
+
+    #[inline(always)]
+    fn escribir(salida: &mut [u8], x: usize, valor: i32) {
+        salida[x * 2..x * 2 + 2].copy_from_slice(&(valor as u16).to_le_bytes());
+    }
+
+    #[inline(always)]
+    fn escribir_bloque(salida: &mut [u8], valores: [i32; BLOQUE]) {
+        let salida: &mut [[u8; 2]; BLOQUE] = salida.as_chunks_mut().0.try_into().unwrap();
+        for (muestra, valor) in salida.iter_mut().zip(valores) {
+            *muestra = (valor as u16).to_le_bytes();
+        }
+    }
+}
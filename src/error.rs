@@ -0,0 +1,167 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// Etapa del procesamiento en la que ocurrió el error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Etapa {
+    Lectura,
+    Decodificacion,
+    Redimension,
+    Conversion,
+    Codificacion,
+    Muxer,
+}
+
+impl fmt::Display for Etapa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nombre = match self {
+            Etapa::Lectura => "lectura",
+            Etapa::Decodificacion => "decodificación",
+            Etapa::Redimension => "redimensión",
+            Etapa::Conversion => "conversión",
+            Etapa::Codificacion => "codificación",
+            Etapa::Muxer => "escritura del contenedor",
+        };
+        f.write_str(nombre)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    // No quedó ninguna imagen para armar el video
+    SinImagenes,
+    // Opciones, patrones o manifiesto inválidos
+    Configuracion(String),
+    // Error de entrada/salida al listar carpetas o leer archivos auxiliares
+    Io { path: PathBuf, fuente: io::Error },
+    // Una imagen no se pudo leer o decodificar
+    Imagen {
+        indice: Option<usize>,
+        path: PathBuf,
+        etapa: Etapa,
+        fuente: image::ImageError,
+    },
+    // Falla de ffmpeg; `indice` es el frame que se estaba enviando, si había uno
+    Ffmpeg {
+        indice: Option<usize>,
+        etapa: Etapa,
+        fuente: ffmpeg_next::Error,
+    },
+    // Un filtro de la cadena rechazó el frame
+    Filtro {
+        indice: usize,
+        path: PathBuf,
+        filtro: String,
+        mensaje: String,
+    },
+    // La codificación se cortó a mitad de camino. `codificados` son los
+    // frames que se llegaron a enviar al encoder y `fallos` los que antes
+    // del corte se omitieron o repitieron. Con lo codificado se intenta
+    // cerrar el video, pero puede no haber quedado (en la primera de dos
+    // pasadas no se escribe nada, y el cierre también puede fallar).
+    Interrumpido {
+        fuente: Box<Error>,
+        codificados: usize,
+        fallos: Vec<Error>,
+    },
+}
+
+impl Error {
+    // Para usar con map_err en las llamadas a ffmpeg
+    pub fn ffmpeg(etapa: Etapa) -> impl FnOnce(ffmpeg_next::Error) -> Error {
+        move |fuente| Error::Ffmpeg {
+            indice: None,
+            etapa,
+            fuente,
+        }
+    }
+
+    pub fn ffmpeg_frame(indice: usize, etapa: Etapa) -> impl FnOnce(ffmpeg_next::Error) -> Error {
+        move |fuente| Error::Ffmpeg {
+            indice: Some(indice),
+            etapa,
+            fuente,
+        }
+    }
+
+    pub fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Error {
+        let path = path.into();
+        move |fuente| Error::Io { path, fuente }
+    }
+
+    // Código de salida del proceso, distinto para cada tipo de falla, para
+    // que los scripts puedan distinguirlas. El 2 queda para los errores de
+    // argumentos que informa clap.
+    pub fn codigo_salida(&self) -> u8 {
+        match self {
+            Error::Configuracion(_) => 2,
+            Error::SinImagenes => 3,
+            Error::Io { .. } => 4,
+            Error::Imagen { .. } | Error::Filtro { .. } => 5,
+            Error::Ffmpeg { etapa: Etapa::Muxer, .. } => 7,
+            Error::Ffmpeg { .. } => 6,
+            Error::Interrumpido { fuente, .. } => fuente.codigo_salida(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::SinImagenes => write!(f, "no se encontraron imágenes"),
+            Error::Configuracion(mensaje) => write!(f, "{}", mensaje),
+            Error::Io { path, fuente } => write!(f, "{}: {}", path.display(), fuente),
+            Error::Imagen {
+                indice,
+                path,
+                etapa,
+                fuente,
+            } => {
+                if let Some(i) = indice {
+                    write!(f, "frame #{} ", i)?;
+                }
+                write!(f, "{} ({}): {}", path.display(), etapa, fuente)
+            }
+            Error::Ffmpeg {
+                indice,
+                etapa,
+                fuente,
+            } => {
+                if let Some(i) = indice {
+                    write!(f, "frame #{} ", i)?;
+                }
+                write!(f, "error de {}: {}", etapa, fuente)
+            }
+            Error::Filtro {
+                indice,
+                path,
+                filtro,
+                mensaje,
+            } => write!(f, "frame #{} {} (filtro {}): {}", indice, path.display(), filtro, mensaje),
+            Error::Interrumpido { fuente, codificados, .. } => {
+                write!(f, "{} (se cortó después de codificar {} frames)", fuente, codificados)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { fuente, .. } => Some(fuente),
+            Error::Imagen { fuente, .. } => Some(fuente),
+            Error::Ffmpeg { fuente, .. } => Some(fuente),
+            Error::Interrumpido { fuente, .. } => Some(fuente.as_ref()),
+            Error::SinImagenes | Error::Configuracion(_) | Error::Filtro { .. } => None,
+        }
+    }
+}
+
+impl From<globset::Error> for Error {
+    fn from(e: globset::Error) -> Self {
+        Error::Configuracion(format!("patrón inválido: {}", e))
+    }
+}
@@ -0,0 +1,532 @@
+use std::f32::consts::PI;
+use std::path::PathBuf;
+use std::str::FromStr;
+use image::imageops::{self, FilterType};
+use image::{GrayImage, RgbImage};
+use rayon::prelude::*;
+
+use crate::error;
+use crate::filtros::{Contexto, FrameFilter};
+use crate::informe;
+
+// Lado mayor de la copia reducida en la que se estima el movimiento
+const LADO_TRABAJO: u32 = 512;
+// Lado de los parches que se comparan; tiene que ser potencia de 2
+const LADO_PARCHE: usize = 128;
+// Pico mínimo de la correlación de fase para confiar en un parche
+const PICO_MINIMO: f32 = 0.08;
+// Error en pixeles de la copia reducida a partir del cual un parche no
+// acompaña al resto (algo se movió en esa zona) y se descarta
+const DESVIO_MAXIMO: f64 = 2.0;
+
+// Contra qué se compara cada frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModoEstabilizacion {
+    // Con el anterior, acumulando; tolera mejor que la escena cambie
+    Consecutivo,
+    // Siempre con el primero; no acumula error pero necesita que buena
+    // parte de la escena siga igual
+    Referencia,
+}
+
+// Movimiento rígido que lleva un punto del frame actual al de referencia:
+// rota `angulo` radianes alrededor del origen y después traslada
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Transformacion {
+    angulo: f64,
+    tx: f64,
+    ty: f64,
+}
+
+impl Transformacion {
+    const IDENTIDAD: Transformacion = Transformacion { angulo: 0.0, tx: 0.0, ty: 0.0 };
+
+    fn aplicar(self, (x, y): (f64, f64)) -> (f64, f64) {
+        let (sin, cos) = self.angulo.sin_cos();
+        (cos * x - sin * y + self.tx, sin * x + cos * y + self.ty)
+    }
+
+    // Aplica primero `otra` y después esta
+    fn componer(self, otra: Transformacion) -> Transformacion {
+        let (tx, ty) = self.aplicar((otra.tx, otra.ty));
+        Transformacion {
+            angulo: self.angulo + otra.angulo,
+            tx,
+            ty,
+        }
+    }
+
+    // Pasa la traslación de la copia reducida a la imagen completa
+    fn escalar(self, escala: f64) -> Transformacion {
+        Transformacion {
+            tx: self.tx * escala,
+            ty: self.ty * escala,
+            ..self
+        }
+    }
+}
+
+// Alinea cada frame con los anteriores para que la base quede quieta aunque
+// alguien mueva el trípode. Estima el desplazamiento (y opcionalmente la
+// rotación) por correlación de fase en parches de una copia reducida, y
+// recorta un margen para que no se vean los bordes al corregir.
+pub struct Estabilizar {
+    modo: ModoEstabilizacion,
+    rotacion: bool,
+    // Fracción que se recorta de cada lado
+    margen: f64,
+    // CSV con la corrección aplicada a cada frame
+    informe: Option<PathBuf>,
+    escritor: Option<informe::Escritor>,
+    entrada: (u32, u32),
+    salida: (u32, u32),
+    // Copia reducida en gris contra la que se compara el próximo frame
+    referencia: Option<GrayImage>,
+    // Corrección del último frame
+    acumulada: Transformacion,
+}
+
+impl Estabilizar {
+    pub fn new(modo: ModoEstabilizacion, rotacion: bool, margen: f64, informe: Option<PathBuf>) -> Self {
+        Estabilizar {
+            modo,
+            rotacion,
+            margen: margen.clamp(0.0, 0.45),
+            informe,
+            escritor: None,
+            entrada: (0, 0),
+            salida: (0, 0),
+            referencia: None,
+            acumulada: Transformacion::IDENTIDAD,
+        }
+    }
+
+    // Dibuja el frame corregido dentro del recorte, con interpolación
+    // bilineal; lo que queda afuera de la imagen repite el borde
+    fn deformar(&self, img: &RgbImage) -> RgbImage {
+        let (w, h) = self.entrada;
+        let (ancho, alto) = self.salida;
+        let x0 = (w - ancho) as f64 / 2.0;
+        let y0 = (h - alto) as f64 / 2.0;
+        let (sin, cos) = self.acumulada.angulo.sin_cos();
+        let Transformacion { tx, ty, .. } = self.acumulada;
+        let maximo_x = (w - 1) as f64;
+        let maximo_y = (h - 1) as f64;
+
+        let mut salida = RgbImage::new(ancho, alto);
+        salida
+            .par_chunks_mut(ancho as usize * 3)
+            .enumerate()
+            .for_each(|(y, fila)| {
+                for (x, pixel) in fila.chunks_exact_mut(3).enumerate() {
+                    // Inversa de la corrección: de la referencia al frame actual
+                    let dx = x as f64 + x0 - tx;
+                    let dy = y as f64 + y0 - ty;
+                    let sx = (cos * dx + sin * dy).clamp(0.0, maximo_x);
+                    let sy = (cos * dy - sin * dx).clamp(0.0, maximo_y);
+                    let (ix, iy) = (sx as u32, sy as u32);
+                    let (fx, fy) = (sx - ix as f64, sy - iy as f64);
+                    let (ix1, iy1) = ((ix + 1).min(w - 1), (iy + 1).min(h - 1));
+                    let (a, b) = (img.get_pixel(ix, iy).0, img.get_pixel(ix1, iy).0);
+                    let (c, d) = (img.get_pixel(ix, iy1).0, img.get_pixel(ix1, iy1).0);
+                    for canal in 0..3 {
+                        let arriba = a[canal] as f64 * (1.0 - fx) + b[canal] as f64 * fx;
+                        let abajo = c[canal] as f64 * (1.0 - fx) + d[canal] as f64 * fx;
+                        pixel[canal] = (arriba * (1.0 - fy) + abajo * fy).round() as u8;
+                    }
+                }
+            });
+        salida
+    }
+
+    fn informar(&mut self, ctx: &Contexto<'_>, parches: usize) -> error::Result<()> {
+        let Some(escritor) = self.escritor.as_mut() else {
+            return Ok(());
+        };
+        // Se informa cuánto se movió el centro de la imagen
+        let centro = (self.entrada.0 as f64 / 2.0, self.entrada.1 as f64 / 2.0);
+        let movido = self.acumulada.aplicar(centro);
+        escritor
+            .write_record([
+                (ctx.indice + 1).to_string(),
+                ctx.frame.path.display().to_string(),
+                format!("{:.2}", movido.0 - centro.0),
+                format!("{:.2}", movido.1 - centro.1),
+                format!("{:.3}", self.acumulada.angulo.to_degrees()),
+                parches.to_string(),
+            ])
+            .and_then(|_| escritor.flush().map_err(csv::Error::from))
+            .map_err(|e| ctx.error("estabilizar", e.to_string()))
+    }
+}
+
+// Parámetros separados por comas: "consecutivo" o "referencia", "rotacion",
+// "margen=5" (porcentaje de cada lado) e "informe=archivo.csv"
+impl FromStr for Estabilizar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modo = ModoEstabilizacion::Consecutivo;
+        let mut rotacion = false;
+        let mut margen = 5.0;
+        let mut informe = None;
+        for parametro in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match parametro.split_once('=') {
+                None if parametro == "consecutivo" => modo = ModoEstabilizacion::Consecutivo,
+                None if parametro == "referencia" => modo = ModoEstabilizacion::Referencia,
+                None if parametro == "rotacion" => rotacion = true,
+                Some(("margen", valor)) => {
+                    margen = valor
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|m| (0.0..45.0).contains(m))
+                        .ok_or_else(|| format!("margen inválido: '{}'", valor))?;
+                }
+                Some(("informe", ruta)) => informe = Some(PathBuf::from(ruta.trim())),
+                _ => return Err(format!("parámetro desconocido: '{}'", parametro)),
+            }
+        }
+        Ok(Estabilizar::new(modo, rotacion, margen / 100.0, informe))
+    }
+}
+
+impl FrameFilter for Estabilizar {
+    fn nombre(&self) -> &str {
+        "estabilizar"
+    }
+
+    fn configurar(&mut self, entrada: (u32, u32)) -> (u32, u32) {
+        let recortar = |lado: u32| ((lado as f64 * (1.0 - 2.0 * self.margen)).round() as u32).clamp(1, lado);
+        self.entrada = entrada;
+        self.salida = (recortar(entrada.0), recortar(entrada.1));
+        self.salida
+    }
+
+    fn reiniciar(&mut self) -> error::Result<()> {
+        self.referencia = None;
+        self.acumulada = Transformacion::IDENTIDAD;
+        let cabecera = ["frame", "archivo", "dx", "dy", "grados", "parches"];
+        informe::reabrir_csv(&mut self.escritor, self.informe.as_deref(), &cabecera)
+    }
+
+    fn aplicar(&mut self, img: RgbImage, ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        let img = match img.dimensions() == self.entrada {
+            true => img,
+            false => imageops::resize(&img, self.entrada.0, self.entrada.1, FilterType::Lanczos3),
+        };
+        let gris = reducir(&img);
+        let escala = self.entrada.0.max(self.entrada.1) as f64 / gris.width().max(gris.height()) as f64;
+
+        // Si no se puede estimar el movimiento se mantiene la última corrección
+        let mut parches = 0;
+        if let Some(referencia) = &self.referencia
+            && let Some((paso, usados)) = estimar(referencia, &gris, self.rotacion)
+        {
+            let paso = paso.escalar(escala);
+            self.acumulada = match self.modo {
+                ModoEstabilizacion::Consecutivo => self.acumulada.componer(paso),
+                ModoEstabilizacion::Referencia => paso,
+            };
+            parches = usados;
+        }
+        if self.referencia.is_none() || self.modo == ModoEstabilizacion::Consecutivo {
+            self.referencia = Some(gris);
+        }
+
+        self.informar(ctx, parches)?;
+        Ok(self.deformar(&img))
+    }
+}
+
+// Copia en gris con el lado mayor en LADO_TRABAJO
+fn reducir(img: &RgbImage) -> GrayImage {
+    let (w, h) = img.dimensions();
+    let escala = (LADO_TRABAJO as f64 / w.max(h) as f64).min(1.0);
+    let ancho = ((w as f64 * escala).round() as u32).max(1);
+    let alto = ((h as f64 * escala).round() as u32).max(1);
+    imageops::grayscale(&imageops::thumbnail(img, ancho, alto))
+}
+
+// Punto del frame actual y dónde está en la referencia
+struct Par {
+    actual: (f64, f64),
+    referencia: (f64, f64),
+    peso: f64,
+}
+
+// Estima la transformación que lleva el frame actual a la referencia,
+// comparando una grilla de 3x3 parches. Devuelve también cuántos parches se
+// usaron, o None si ninguno es confiable.
+fn estimar(referencia: &GrayImage, actual: &GrayImage, rotacion: bool) -> Option<(Transformacion, usize)> {
+    let (w, h) = referencia.dimensions();
+    if actual.dimensions() != (w, h) {
+        return None;
+    }
+    // El parche más grande que entra dos veces en la imagen
+    let mut lado = LADO_PARCHE;
+    while lado > 16 && lado as u32 * 2 > w.min(h) {
+        lado /= 2;
+    }
+    if lado as u32 > w.min(h) {
+        return None;
+    }
+    let ventana = hann(lado);
+
+    let mut pares = Vec::new();
+    for i in 1..=3 {
+        for j in 1..=3 {
+            let mitad = lado as u32 / 2;
+            let cx = (w * i / 4).clamp(mitad, w - mitad);
+            let cy = (h * j / 4).clamp(mitad, h - mitad);
+            let a = parche(referencia, cx - mitad, cy - mitad, lado, &ventana);
+            let b = parche(actual, cx - mitad, cy - mitad, lado, &ventana);
+            if let Some((dx, dy, pico)) = correlacion(a, b, lado)
+                && pico >= PICO_MINIMO
+            {
+                // El contenido que en la referencia está en el centro del
+                // parche aparece desplazado en el frame actual
+                let centro = (cx as f64, cy as f64);
+                pares.push(Par {
+                    actual: (centro.0 + dx, centro.1 + dy),
+                    referencia: centro,
+                    peso: pico as f64,
+                });
+            }
+        }
+    }
+
+    // Se ajusta, se descartan los parches que no acompañan y se vuelve a ajustar
+    let transformacion = ajustar(&pares, rotacion)?;
+    pares.retain(|p| {
+        let (x, y) = transformacion.aplicar(p.actual);
+        (x - p.referencia.0).hypot(y - p.referencia.1) <= DESVIO_MAXIMO
+    });
+    let transformacion = ajustar(&pares, rotacion)?;
+    Some((transformacion, pares.len()))
+}
+
+// Movimiento rígido por mínimos cuadrados ponderados. Con un solo par no
+// se puede estimar la rotación y se ajusta solo la traslación.
+fn ajustar(pares: &[Par], rotacion: bool) -> Option<Transformacion> {
+    let total: f64 = pares.iter().map(|p| p.peso).sum();
+    if pares.is_empty() || total <= 0.0 {
+        return None;
+    }
+    let media = |f: fn(&Par) -> f64| pares.iter().map(|p| f(p) * p.peso).sum::<f64>() / total;
+    let actual = (media(|p| p.actual.0), media(|p| p.actual.1));
+    let referencia = (media(|p| p.referencia.0), media(|p| p.referencia.1));
+
+    let mut angulo = 0.0;
+    if rotacion && pares.len() > 1 {
+        let (mut seno, mut coseno) = (0.0, 0.0);
+        for p in pares {
+            let (ax, ay) = (p.actual.0 - actual.0, p.actual.1 - actual.1);
+            let (bx, by) = (p.referencia.0 - referencia.0, p.referencia.1 - referencia.1);
+            seno += p.peso * (ax * by - ay * bx);
+            coseno += p.peso * (ax * bx + ay * by);
+        }
+        angulo = f64::atan2(seno, coseno);
+    }
+    let rotada = Transformacion { angulo, tx: 0.0, ty: 0.0 }.aplicar(actual);
+    Some(Transformacion {
+        angulo,
+        tx: referencia.0 - rotada.0,
+        ty: referencia.1 - rotada.1,
+    })
+}
+
+fn hann(lado: usize) -> Vec<f32> {
+    (0..lado)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / lado as f32).cos())
+        .collect()
+}
+
+// Parche sin el nivel medio y con ventana, listo para la FFT
+fn parche(img: &GrayImage, x0: u32, y0: u32, lado: usize, ventana: &[f32]) -> Vec<(f32, f32)> {
+    let mut valores: Vec<f32> = (0..lado * lado)
+        .map(|i| img.get_pixel(x0 + (i % lado) as u32, y0 + (i / lado) as u32).0[0] as f32)
+        .collect();
+    let media = valores.iter().sum::<f32>() / valores.len() as f32;
+    for (i, valor) in valores.iter_mut().enumerate() {
+        *valor = (*valor - media) * ventana[i % lado] * ventana[i / lado];
+    }
+    valores.into_iter().map(|v| (v, 0.0)).collect()
+}
+
+// Correlación de fase: desplazamiento de `actual` respecto de `referencia`
+// con precisión de subpixel, y la altura del pico (cerca de 1 si coinciden)
+fn correlacion(mut referencia: Vec<(f32, f32)>, mut actual: Vec<(f32, f32)>, lado: usize) -> Option<(f64, f64, f32)> {
+    fft_2d(&mut referencia, lado, false);
+    fft_2d(&mut actual, lado, false);
+    // Espectro cruzado normalizado: actual * conj(referencia) / |...|
+    for (a, r) in actual.iter_mut().zip(&referencia) {
+        let re = a.0 * r.0 + a.1 * r.1;
+        let im = a.1 * r.0 - a.0 * r.1;
+        let modulo = re.hypot(im);
+        *a = if modulo > 1e-6 { (re / modulo, im / modulo) } else { (0.0, 0.0) };
+    }
+    fft_2d(&mut actual, lado, true);
+
+    let normalizar = 1.0 / (lado * lado) as f32;
+    let valor = |x: usize, y: usize| actual[(y % lado) * lado + x % lado].0 * normalizar;
+    let (pico, indice) = actual
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (v.0 * normalizar, i))
+        .max_by(|a, b| a.0.total_cmp(&b.0))?;
+    let (px, py) = (indice % lado, indice / lado);
+
+    // Parábola por el pico y sus vecinos para la parte fraccionaria
+    let subpixel = |antes: f32, despues: f32| {
+        let denominador = antes - 2.0 * pico + despues;
+        match denominador.abs() > 1e-6 {
+            true => ((antes - despues) / (2.0 * denominador)).clamp(-0.5, 0.5) as f64,
+            false => 0.0,
+        }
+    };
+    let fx = subpixel(valor(px + lado - 1, py), valor(px + 1, py));
+    let fy = subpixel(valor(px, py + lado - 1), valor(px, py + 1));
+    // Los índices de la mitad superior son desplazamientos negativos
+    let desplazamiento = |p: usize| if p > lado / 2 { p as f64 - lado as f64 } else { p as f64 };
+    Some((desplazamiento(px) + fx, desplazamiento(py) + fy, pico))
+}
+
+// FFT de Cooley-Tukey sobre las filas y después sobre las columnas
+fn fft_2d(datos: &mut [(f32, f32)], lado: usize, inversa: bool) {
+    for fila in datos.chunks_exact_mut(lado) {
+        fft(fila, inversa);
+    }
+    let mut columna = vec![(0.0, 0.0); lado];
+    for x in 0..lado {
+        for (y, valor) in columna.iter_mut().enumerate() {
+            *valor = datos[y * lado + x];
+        }
+        fft(&mut columna, inversa);
+        for (y, valor) in columna.iter().enumerate() {
+            datos[y * lado + x] = *valor;
+        }
+    }
+}
+
+// FFT in-place de largo potencia de 2, sin normalizar
+fn fft(datos: &mut [(f32, f32)], inversa: bool) {
+    let n = datos.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            datos.swap(i, j);
+        }
+    }
+
+    let signo = if inversa { 1.0 } else { -1.0 };
+    let mut largo = 2;
+    while largo <= n {
+        let (sin, cos) = (signo * 2.0 * PI / largo as f32).sin_cos();
+        for bloque in datos.chunks_exact_mut(largo) {
+            let (izquierda, derecha) = bloque.split_at_mut(largo / 2);
+            let (mut wr, mut wi) = (1.0f32, 0.0f32);
+            for (a, b) in izquierda.iter_mut().zip(derecha.iter_mut()) {
+                let t = (b.0 * wr - b.1 * wi, b.0 * wi + b.1 * wr);
+                *b = (a.0 - t.0, a.1 - t.1);
+                *a = (a.0 + t.0, a.1 + t.1);
+                (wr, wi) = (wr * cos - wi * sin, wr * sin + wi * cos);
+            }
+        }
+        largo <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Valores pseudoaleatorios reproducibles entre 0 y 1
+    fn aleatorios(cantidad: usize) -> Vec<f32> {
+        let mut semilla = 12345u32;
+        (0..cantidad)
+            .map(|_| {
+                semilla = semilla.wrapping_mul(1103515245).wrapping_add(12345);
+                (semilla >> 16) as f32 / 65_536.0
+            })
+            .collect()
+    }
+
+    // Ruido suave: valores al azar en una grilla de `paso` pixeles
+    // interpolados entre sí. No se repite, así la correlación tiene un solo
+    // pico; con una textura periódica cualquier múltiplo del período sirve.
+    fn textura(lado: u32, paso: f64, (dx, dy): (f64, f64)) -> GrayImage {
+        let nodos = (lado as f64 / paso) as usize + 3;
+        let grilla = aleatorios(nodos * nodos);
+        let valor = |x: f64, y: f64| {
+            let (gx, gy) = (x / paso + 1.0, y / paso + 1.0);
+            let (ix, iy) = (gx.floor() as usize, gy.floor() as usize);
+            let (fx, fy) = (gx.fract() as f32, gy.fract() as f32);
+            let nodo = |i: usize, j: usize| grilla[j * nodos + i];
+            let arriba = nodo(ix, iy) * (1.0 - fx) + nodo(ix + 1, iy) * fx;
+            let abajo = nodo(ix, iy + 1) * (1.0 - fx) + nodo(ix + 1, iy + 1) * fx;
+            arriba * (1.0 - fy) + abajo * fy
+        };
+        // El contenido se mueve (dx, dy) respecto de la textura sin desplazar
+        GrayImage::from_fn(lado, lado, |x, y| {
+            image::Luma([(255.0 * valor(x as f64 - dx, y as f64 - dy)).round() as u8])
+        })
+    }
+
+    #[test]
+    fn fft_ida_y_vuelta() {
+        let valores = aleatorios(128);
+        let original: Vec<(f32, f32)> = valores.chunks_exact(2).map(|c| (c[0], c[1])).collect();
+        let mut datos = original.clone();
+        fft_2d(&mut datos, 8, false);
+        assert!(datos.iter().zip(&original).any(|(a, b)| (a.0 - b.0).abs() > 1e-3));
+        fft_2d(&mut datos, 8, true);
+        // La inversa no normaliza
+        for (a, b) in datos.iter().zip(&original) {
+            assert!((a.0 / 64.0 - b.0).abs() < 1e-5 && (a.1 / 64.0 - b.1).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn recupera_un_desplazamiento_con_subpixel() {
+        let referencia = textura(256, 6.0, (0.0, 0.0));
+        let actual = textura(256, 6.0, (3.5, -2.5));
+        let (transformacion, parches) = estimar(&referencia, &actual, false).expect("sin estimación");
+        assert!(parches >= 5, "solo {} parches", parches);
+        // Lleva el frame actual a la referencia, o sea que deshace el movimiento
+        assert!((transformacion.tx + 3.5).abs() < 0.1, "{:?}", transformacion);
+        assert!((transformacion.ty - 2.5).abs() < 0.1, "{:?}", transformacion);
+        assert_eq!(transformacion.angulo, 0.0);
+    }
+
+    #[test]
+    fn ajusta_una_grilla_rotada() {
+        let esperada = Transformacion {
+            angulo: 3f64.to_radians(),
+            tx: 4.5,
+            ty: -7.25,
+        };
+        let pares: Vec<Par> = (1..=3)
+            .flat_map(|i| (1..=3).map(move |j| (i as f64 * 64.0, j as f64 * 48.0)))
+            .map(|actual| Par {
+                actual,
+                referencia: esperada.aplicar(actual),
+                peso: 1.0,
+            })
+            .collect();
+        let ajustada = ajustar(&pares, true).expect("sin ajuste");
+        assert!((ajustada.angulo - esperada.angulo).abs() < 1e-9, "{:?}", ajustada);
+        assert!((ajustada.tx - esperada.tx).abs() < 1e-6, "{:?}", ajustada);
+        assert!((ajustada.ty - esperada.ty).abs() < 1e-6, "{:?}", ajustada);
+
+        // Sin rotación queda la traslación que mejor aproxima
+        let solo_traslacion = ajustar(&pares, false).expect("sin ajuste");
+        assert_eq!(solo_traslacion.angulo, 0.0);
+    }
+}
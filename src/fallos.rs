@@ -0,0 +1,31 @@
+use clap::ValueEnum;
+
+use crate::error::Error;
+
+// Qué hacer cuando un frame no se puede leer
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Politica {
+    // Se deja de procesar, pero el video queda cerrado con lo ya codificado
+    #[default]
+    Abort,
+    // Se descarta el frame y el resto se corre para no dejar un hueco
+    Skip,
+    // Se vuelve a mostrar el último frame bueno durante lo que dura este
+    RepeatPrevious,
+}
+
+// Resumen de los frames que no se pudieron usar
+pub fn reportar(fallos: &[Error], politica: Politica) {
+    if fallos.is_empty() {
+        return;
+    }
+    let accion = match politica {
+        Politica::Abort => "se detuvo el proceso",
+        Politica::Skip => "se omitieron",
+        Politica::RepeatPrevious => "se repitió el frame anterior",
+    };
+    println!("\nFrames con errores ({}, {}):", fallos.len(), accion);
+    for fallo in fallos {
+        println!("  {}", fallo);
+    }
+}
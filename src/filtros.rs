@@ -0,0 +1,580 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use image::{Rgb, RgbImage};
+
+use crate::antiparpadeo::Antiparpadeo;
+use crate::balance::Balance;
+use crate::error::{self, Error};
+use crate::estabilizacion::Estabilizar;
+use crate::frames::{Frame, Recorte};
+use crate::mediana::Mediana;
+use crate::texto;
+
+// Frame que se está filtrando
+pub struct Contexto<'a> {
+    pub indice: usize,
+    pub frame: &'a Frame,
+}
+
+impl Contexto<'_> {
+    // Error de un filtro con la ruta y el índice del frame
+    pub fn error(&self, filtro: &str, mensaje: impl Into<String>) -> Error {
+        Error::Filtro {
+            indice: self.indice,
+            path: self.frame.path.clone(),
+            filtro: filtro.to_string(),
+            mensaje: mensaje.into(),
+        }
+    }
+}
+
+// Transformación sobre la imagen decodificada, antes de convertirla a YUV.
+// Los filtros forman una cadena y cada uno recibe la salida del anterior.
+pub trait FrameFilter: Send {
+    fn nombre(&self) -> &str;
+
+    // Copia del filtro para los hilos de precarga. Los filtros que no
+    // dependen de los frames anteriores devuelven una y se corren en
+    // paralelo; a partir del primero que devuelve None la cadena se aplica
+    // en orden en el hilo que codifica.
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        None
+    }
+
+    // Se llama una vez antes de procesar con las dimensiones que tendrá la
+    // primera imagen al llegar a este filtro. Devuelve con cuáles sale; la
+    // salida del último filtro es la resolución del video.
+    fn configurar(&mut self, entrada: (u32, u32)) -> (u32, u32) {
+        entrada
+    }
+
+    // Se llama antes de cada pasada por los frames, para que los filtros
+    // que guardan estado entre frames empiecen de cero
+    fn reiniciar(&mut self) -> error::Result<()> {
+        Ok(())
+    }
+
+    fn aplicar(&mut self, img: RgbImage, ctx: &Contexto<'_>) -> error::Result<RgbImage>;
+
+    // Recibe un frame y devuelve los que ya salen del filtro, en orden. Por
+    // defecto cada frame sale enseguida por `aplicar`. Un filtro que necesita
+    // ver frames posteriores, como la mediana, guarda algunos y los devuelve
+    // en las llamadas siguientes o en `terminar`; esos filtros no se pueden
+    // duplicar.
+    fn recibir(&mut self, img: RgbImage, ctx: &Contexto<'_>) -> error::Result<Vec<RgbImage>> {
+        Ok(vec![self.aplicar(img, ctx)?])
+    }
+
+    // Se llama después del último frame y devuelve los que quedaron guardados
+    fn terminar(&mut self) -> error::Result<Vec<RgbImage>> {
+        Ok(Vec::new())
+    }
+}
+
+// Lleva cada imagen a un tamaño fijo. Sin tamaño explícito usa el de la
+// primera imagen, así los frames que no coinciden se ajustan a ella.
+#[derive(Clone)]
+pub struct Redimensionar {
+    tamano: Option<(u32, u32)>,
+    metodo: FilterType,
+}
+
+impl Redimensionar {
+    pub fn new(tamano: Option<(u32, u32)>, metodo: FilterType) -> Self {
+        Redimensionar { tamano, metodo }
+    }
+}
+
+impl Default for Redimensionar {
+    fn default() -> Self {
+        Redimensionar::new(None, FilterType::Lanczos3)
+    }
+}
+
+impl FrameFilter for Redimensionar {
+    fn nombre(&self) -> &str {
+        "redimensionar"
+    }
+
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn configurar(&mut self, entrada: (u32, u32)) -> (u32, u32) {
+        *self.tamano.get_or_insert(entrada)
+    }
+
+    fn aplicar(&mut self, img: RgbImage, _ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        let (width, height) = self.tamano.unwrap_or(img.dimensions());
+        if img.dimensions() == (width, height) {
+            return Ok(img);
+        }
+        Ok(imageops::resize(&img, width, height, self.metodo))
+    }
+}
+
+// Recorta el mismo rectángulo de todas las imágenes
+#[derive(Clone)]
+pub struct Recortar(pub Recorte);
+
+impl FrameFilter for Recortar {
+    fn nombre(&self) -> &str {
+        "recortar"
+    }
+
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn configurar(&mut self, entrada: (u32, u32)) -> (u32, u32) {
+        let x = self.0.x.min(entrada.0.saturating_sub(1));
+        let y = self.0.y.min(entrada.1.saturating_sub(1));
+        (
+            self.0.ancho.min(entrada.0 - x).max(1),
+            self.0.alto.min(entrada.1 - y).max(1),
+        )
+    }
+
+    fn aplicar(&mut self, img: RgbImage, _ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        Ok(self.0.aplicar(&img.into()).to_rgb8())
+    }
+}
+
+// Rota 90, 180 o 270 grados en sentido horario
+#[derive(Clone)]
+pub struct Rotar(pub u32);
+
+impl FrameFilter for Rotar {
+    fn nombre(&self) -> &str {
+        "rotar"
+    }
+
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn configurar(&mut self, (w, h): (u32, u32)) -> (u32, u32) {
+        if self.0 == 180 { (w, h) } else { (h, w) }
+    }
+
+    fn aplicar(&mut self, img: RgbImage, _ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        Ok(match self.0 {
+            90 => imageops::rotate90(&img),
+            180 => imageops::rotate180(&img),
+            _ => imageops::rotate270(&img),
+        })
+    }
+}
+
+// Invierte la imagen horizontal o verticalmente
+#[derive(Clone)]
+pub struct Espejo {
+    pub vertical: bool,
+}
+
+impl FrameFilter for Espejo {
+    fn nombre(&self) -> &str {
+        "espejo"
+    }
+
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn aplicar(&mut self, mut img: RgbImage, _ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        if self.vertical {
+            imageops::flip_vertical_in_place(&mut img);
+        } else {
+            imageops::flip_horizontal_in_place(&mut img);
+        }
+        Ok(img)
+    }
+}
+
+// Corrección de color simple: suma brillo y ajusta el contraste
+#[derive(Clone)]
+pub struct Color {
+    pub brillo: i32,
+    pub contraste: f32,
+}
+
+impl FrameFilter for Color {
+    fn nombre(&self) -> &str {
+        "color"
+    }
+
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn aplicar(&mut self, mut img: RgbImage, _ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        if self.brillo != 0 {
+            imageops::colorops::brighten_in_place(&mut img, self.brillo);
+        }
+        if self.contraste != 0.0 {
+            imageops::colorops::contrast_in_place(&mut img, self.contraste);
+        }
+        Ok(img)
+    }
+}
+
+// Texto fijo sobre cada imagen; "{n}" se reemplaza por el número de frame
+#[derive(Clone)]
+pub struct Rotulo(pub String);
+
+impl FrameFilter for Rotulo {
+    fn nombre(&self) -> &str {
+        "texto"
+    }
+
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn aplicar(&mut self, mut img: RgbImage, ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        let texto = self.0.replace("{n}", &(ctx.indice + 1).to_string());
+        texto::leyenda(&mut img, &texto);
+        Ok(img)
+    }
+}
+
+// Resolución del video: fija o con un lado calculado según la proporción
+// de la primera imagen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolucion {
+    Fija(u32, u32),
+    Ancho(u32),
+    Alto(u32),
+}
+
+impl Resolucion {
+    pub fn resolver(self, (w, h): (u32, u32)) -> (u32, u32) {
+        let proporcional = |lado: u32, num: u32, den: u32| {
+            ((lado as f64 * num as f64 / den as f64).round() as u32).max(1)
+        };
+        match self {
+            Resolucion::Fija(ancho, alto) => (ancho, alto),
+            Resolucion::Ancho(ancho) => (ancho, proporcional(ancho, h, w)),
+            Resolucion::Alto(alto) => (proporcional(alto, w, h), alto),
+        }
+    }
+}
+
+// "1920x1080", "1280x" o "1280" (alto automático), "x720" (ancho
+// automático), "720p"/"1080p"/"2160p" (16:9), "4k" u "8k"
+impl FromStr for Resolucion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let invalida = || format!("resolución inválida: '{}'", s);
+        let lado = |v: &str| v.parse::<u32>().ok().filter(|&v| v > 0);
+        let resolucion = match s.as_str() {
+            "4k" => Resolucion::Fija(3840, 2160),
+            "8k" => Resolucion::Fija(7680, 4320),
+            _ => {
+                if let Some(alto) = s.strip_suffix('p') {
+                    let alto = lado(alto).ok_or_else(invalida)?;
+                    // Ancho par más cercano a 16:9, p. ej. 480p es 854x480
+                    let ancho = (alto as f64 * 16.0 / 9.0 / 2.0).round() as u32 * 2;
+                    Resolucion::Fija(ancho, alto)
+                } else {
+                    match s.split_once('x') {
+                        Some((ancho, "")) => Resolucion::Ancho(lado(ancho).ok_or_else(invalida)?),
+                        Some(("", alto)) => Resolucion::Alto(lado(alto).ok_or_else(invalida)?),
+                        Some((ancho, alto)) => Resolucion::Fija(
+                            lado(ancho).ok_or_else(invalida)?,
+                            lado(alto).ok_or_else(invalida)?,
+                        ),
+                        None => Resolucion::Ancho(lado(&s).ok_or_else(invalida)?),
+                    }
+                }
+            }
+        };
+        Ok(resolucion)
+    }
+}
+
+// Cómo entra cada imagen en la resolución del video
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ModoAjuste {
+    // Entra entera y sobran bandas del color de relleno
+    #[default]
+    #[value(alias = "fit", alias = "letterbox")]
+    Encajar,
+    // Cubre todo el cuadro y se recorta lo que sobra
+    #[value(alias = "fill", alias = "crop")]
+    Llenar,
+    // Se deforma para ocupar el cuadro exacto
+    #[value(alias = "stretch")]
+    Estirar,
+}
+
+// Lleva cada imagen a la resolución del video sin deformarla (salvo con
+// ModoAjuste::Estirar). Sin resolución usa la de la primera imagen.
+#[derive(Clone)]
+pub struct Encuadrar {
+    resolucion: Option<Resolucion>,
+    modo: ModoAjuste,
+    fondo: Rgb<u8>,
+    tamano: (u32, u32),
+}
+
+impl Encuadrar {
+    pub fn new(resolucion: Option<Resolucion>, modo: ModoAjuste, fondo: Rgb<u8>) -> Self {
+        Encuadrar {
+            resolucion,
+            modo,
+            fondo,
+            tamano: (0, 0),
+        }
+    }
+}
+
+impl FrameFilter for Encuadrar {
+    fn nombre(&self) -> &str {
+        "encuadrar"
+    }
+
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn configurar(&mut self, entrada: (u32, u32)) -> (u32, u32) {
+        self.tamano = self.resolucion.map_or(entrada, |r| r.resolver(entrada));
+        self.tamano
+    }
+
+    fn aplicar(&mut self, img: RgbImage, _ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        let (width, height) = self.tamano;
+        let (w, h) = img.dimensions();
+        if (w, h) == (width, height) {
+            return Ok(img);
+        }
+        let escala_x = width as f64 / w as f64;
+        let escala_y = height as f64 / h as f64;
+        let escala = match self.modo {
+            ModoAjuste::Estirar => return Ok(imageops::resize(&img, width, height, FilterType::Lanczos3)),
+            ModoAjuste::Encajar => escala_x.min(escala_y),
+            ModoAjuste::Llenar => escala_x.max(escala_y),
+        };
+        let escalar = |lado: u32, limite: u32| {
+            let lado = ((lado as f64 * escala).round() as u32).max(1);
+            match self.modo {
+                ModoAjuste::Encajar => lado.min(limite),
+                _ => lado.max(limite),
+            }
+        };
+        let (nw, nh) = (escalar(w, width), escalar(h, height));
+        let img = imageops::resize(&img, nw, nh, FilterType::Lanczos3);
+
+        if self.modo == ModoAjuste::Llenar {
+            let x = (nw - width) / 2;
+            let y = (nh - height) / 2;
+            return Ok(imageops::crop_imm(&img, x, y, width, height).to_image());
+        }
+        let mut cuadro = RgbImage::from_pixel(width, height, self.fondo);
+        imageops::replace(&mut cuadro, &img, ((width - nw) / 2) as i64, ((height - nh) / 2) as i64);
+        Ok(cuadro)
+    }
+}
+
+// Color como "#rrggbb", "rrggbb", "r,g,b" o por nombre (negro, blanco, gris)
+pub fn parse_color(s: &str) -> Result<Rgb<u8>, String> {
+    let s = s.trim();
+    let invalido = || format!("color inválido: '{}'", s);
+    match s.to_lowercase().as_str() {
+        "negro" => return Ok(Rgb([0, 0, 0])),
+        "blanco" => return Ok(Rgb([255, 255, 255])),
+        "gris" => return Ok(Rgb([128, 128, 128])),
+        _ => {}
+    }
+    if s.contains(',') {
+        let canales = s
+            .split(',')
+            .map(|c| c.trim().parse::<u8>().map_err(|_| invalido()))
+            .collect::<Result<Vec<_>, _>>()?;
+        return match canales[..] {
+            [r, g, b] => Ok(Rgb([r, g, b])),
+            _ => Err(invalido()),
+        };
+    }
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalido());
+    }
+    let canal = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalido());
+    Ok(Rgb([canal(0)?, canal(2)?, canal(4)?]))
+}
+
+// Fuerza de una corrección, de 0 (no corrige) a 1 (corrige del todo)
+pub fn parse_fuerza(s: &str) -> Result<f64, String> {
+    s.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|f| (0.0..=1.0).contains(f))
+        .ok_or_else(|| format!("la fuerza va de 0 a 1: '{}'", s))
+}
+
+// Tabla de 8 bits que multiplica cada valor por `ganancia`
+pub fn tabla_ganancia(ganancia: f64) -> [u8; 256] {
+    std::array::from_fn(|i| (i as f64 * ganancia).round().clamp(0.0, 255.0) as u8)
+}
+
+// Cómo se llevan las dimensiones a las que acepta el formato de pixel
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AjusteDimensiones {
+    // Agranda hasta el múltiplo siguiente repitiendo el borde
+    Rellenar,
+    // Achica hasta el múltiplo anterior recortando por los lados
+    #[default]
+    Recortar,
+    // Escala al múltiplo más cercano
+    Escalar,
+}
+
+// Último filtro de la cadena cuando la resolución no es múltiplo de lo que
+// pide el submuestreo de croma (2 en 4:2:0) o de 16 si se pidió
+#[derive(Clone)]
+pub struct Ajustar {
+    modo: AjusteDimensiones,
+    multiplo: (u32, u32),
+    entrada: (u32, u32),
+    tamano: (u32, u32),
+}
+
+impl Ajustar {
+    pub fn new(modo: AjusteDimensiones, multiplo: (u32, u32)) -> Self {
+        Ajustar {
+            modo,
+            multiplo,
+            entrada: (0, 0),
+            tamano: (0, 0),
+        }
+    }
+}
+
+impl FrameFilter for Ajustar {
+    fn nombre(&self) -> &str {
+        "ajustar"
+    }
+
+    fn duplicar(&self) -> Option<Box<dyn FrameFilter>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn configurar(&mut self, entrada: (u32, u32)) -> (u32, u32) {
+        let ajustar = |valor: u32, multiplo: u32| {
+            let valor = match self.modo {
+                AjusteDimensiones::Rellenar => valor.div_ceil(multiplo) * multiplo,
+                AjusteDimensiones::Recortar => valor / multiplo * multiplo,
+                AjusteDimensiones::Escalar => (valor + multiplo / 2) / multiplo * multiplo,
+            };
+            valor.max(multiplo)
+        };
+        self.entrada = entrada;
+        self.tamano = (ajustar(entrada.0, self.multiplo.0), ajustar(entrada.1, self.multiplo.1));
+        self.tamano
+    }
+
+    fn aplicar(&mut self, img: RgbImage, _ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        let (width, height) = self.tamano;
+        if img.dimensions() == (width, height) {
+            return Ok(img);
+        }
+        // Las imágenes de otro tamaño que la primera se escalan directamente
+        if self.modo == AjusteDimensiones::Escalar || img.dimensions() != self.entrada {
+            return Ok(imageops::resize(&img, width, height, FilterType::Lanczos3));
+        }
+        // Al recortar se quita lo mismo de cada lado; al rellenar se repiten
+        // la última columna y la última fila
+        let (w, h) = img.dimensions();
+        let x0 = w.saturating_sub(width) / 2;
+        let y0 = h.saturating_sub(height) / 2;
+        Ok(RgbImage::from_fn(width, height, |x, y| {
+            *img.get_pixel((x0 + x).min(w - 1), (y0 + y).min(h - 1))
+        }))
+    }
+}
+
+// Crea un filtro desde su descripción "nombre=parámetros", p. ej.
+// "redimensionar=1280x720,lanczos3", "recortar=0,0,4000,3000", "rotar=90",
+// "espejo=vertical", "color=10,15" (brillo, contraste), "texto=Día {n}",
+// "estabilizar=rotacion,margen=5,informe=estabilizacion.csv",
+// "antiparpadeo=ventana=15,fuerza=0.8,curva=brillo.csv",
+// "balance=zona=100:80:400:300" o "mediana=ventana=5"
+pub fn crear(descripcion: &str) -> error::Result<Box<dyn FrameFilter>> {
+    let (nombre, parametros) = descripcion
+        .split_once('=')
+        .map_or((descripcion.trim(), ""), |(n, p)| (n.trim(), p.trim()));
+    let invalido = |mensaje: &str| {
+        Error::Configuracion(format!("filtro '{}': {}", descripcion, mensaje))
+    };
+
+    let filtro: Box<dyn FrameFilter> = match nombre {
+        "redimensionar" => {
+            let mut tamano = None;
+            let mut metodo = FilterType::Lanczos3;
+            for parametro in parametros.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                if let Some((w, h)) = parametro.split_once('x') {
+                    let w = w.parse().map_err(|_| invalido("ancho inválido"))?;
+                    let h = h.parse().map_err(|_| invalido("alto inválido"))?;
+                    tamano = Some((w, h));
+                } else {
+                    metodo = parse_metodo(parametro).ok_or_else(|| invalido("método desconocido"))?;
+                }
+            }
+            Box::new(Redimensionar::new(tamano, metodo))
+        }
+        "recortar" => Box::new(Recortar(parametros.parse().map_err(|e: String| invalido(&e))?)),
+        "rotar" => match parametros {
+            "90" | "180" | "270" => Box::new(Rotar(parametros.parse().unwrap())),
+            _ => return Err(invalido("el ángulo debe ser 90, 180 o 270")),
+        },
+        "espejo" => match parametros {
+            "" | "horizontal" => Box::new(Espejo { vertical: false }),
+            "vertical" => Box::new(Espejo { vertical: true }),
+            _ => return Err(invalido("se esperaba horizontal o vertical")),
+        },
+        "color" => {
+            let (brillo, contraste) = parametros.split_once(',').unwrap_or((parametros, "0"));
+            Box::new(Color {
+                brillo: brillo.trim().parse().map_err(|_| invalido("brillo inválido"))?,
+                contraste: contraste.trim().parse().map_err(|_| invalido("contraste inválido"))?,
+            })
+        }
+        "texto" => Box::new(Rotulo(parametros.to_string())),
+        "estabilizar" => Box::new(parametros.parse::<Estabilizar>().map_err(|e| invalido(&e))?),
+        "antiparpadeo" => Box::new(parametros.parse::<Antiparpadeo>().map_err(|e| invalido(&e))?),
+        "balance" => Box::new(parametros.parse::<Balance>().map_err(|e| invalido(&e))?),
+        "mediana" => Box::new(parametros.parse::<Mediana>().map_err(|e| invalido(&e))?),
+        _ => return Err(invalido("filtro desconocido")),
+    };
+    Ok(filtro)
+}
+
+fn parse_metodo(nombre: &str) -> Option<FilterType> {
+    match nombre {
+        "nearest" => Some(FilterType::Nearest),
+        "triangle" => Some(FilterType::Triangle),
+        "catmullrom" => Some(FilterType::CatmullRom),
+        "gaussian" => Some(FilterType::Gaussian),
+        "lanczos3" => Some(FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+// Archivo con un filtro por línea en el mismo formato que --filtro. Las
+// líneas vacías y las que empiezan con # se ignoran.
+pub fn leer_archivo(path: &Path) -> error::Result<Vec<Box<dyn FrameFilter>>> {
+    let contenido = fs::read_to_string(path).map_err(Error::io(path))?;
+    contenido
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(crear)
+        .collect()
+}
@@ -0,0 +1,291 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use clap::ValueEnum;
+
+use crate::error::{self, Error};
+use crate::metadatos;
+use crate::orden::{self, Orden};
+use crate::seleccion::Seleccion;
+
+// Imagen de entrada junto con los metadatos leídos de ella
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub path: PathBuf,
+    // Momento de captura en milisegundos, si la imagen trae EXIF
+    pub capturado: Option<i64>,
+    // Duración fija en milisegundos que reemplaza a la calculada
+    pub duracion: Option<i64>,
+    // Si está presente el frame es un cuadro de título y no se lee `path`
+    pub titulo: Option<String>,
+    // Texto que se dibuja sobre la imagen
+    pub leyenda: Option<String>,
+    // Región de la imagen original (ya girada según EXIF) que se usa, antes
+    // de redimensionar
+    pub recorte: Option<Recorte>,
+}
+
+impl Frame {
+    pub fn new(path: PathBuf) -> Self {
+        let meta = metadatos::leer(&path);
+        Frame {
+            path,
+            capturado: meta.capturado,
+            duracion: None,
+            titulo: None,
+            leyenda: None,
+            recorte: None,
+        }
+    }
+
+    // Cuadro negro con texto; `path` es la carpeta de la sesión que presenta
+    pub fn tarjeta(path: PathBuf, titulo: String, duracion: i64) -> Self {
+        Frame {
+            path,
+            capturado: None,
+            duracion: Some(duracion),
+            titulo: Some(titulo),
+            leyenda: None,
+            recorte: None,
+        }
+    }
+}
+
+// Rectángulo en pixeles de la imagen original, escrito como "x,y,ancho,alto"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recorte {
+    pub x: u32,
+    pub y: u32,
+    pub ancho: u32,
+    pub alto: u32,
+}
+
+impl Recorte {
+    // Recorta la imagen; la parte que cae fuera de ella se ignora
+    pub fn aplicar(&self, img: &image::DynamicImage) -> image::DynamicImage {
+        let x = self.x.min(img.width().saturating_sub(1));
+        let y = self.y.min(img.height().saturating_sub(1));
+        let ancho = self.ancho.min(img.width() - x).max(1);
+        let alto = self.alto.min(img.height() - y).max(1);
+        img.crop_imm(x, y, ancho, alto)
+    }
+}
+
+impl FromStr for Recorte {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valores: Vec<u32> = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("recorte inválido, se esperaba x,y,ancho,alto: '{}'", s))?;
+        match valores[..] {
+            [x, y, ancho, alto] if ancho > 0 && alto > 0 => Ok(Recorte { x, y, ancho, alto }),
+            _ => Err(format!("recorte inválido, se esperaba x,y,ancho,alto: '{}'", s)),
+        }
+    }
+}
+
+// Archivo de la carpeta que no se usa como frame
+#[derive(Debug, Clone)]
+pub struct Omitido {
+    pub path: PathBuf,
+    pub motivo: String,
+}
+
+// Lee las imágenes de las carpetas con sus metadatos y las ordena en una
+// sola línea de tiempo. Los archivos que no son imágenes decodificables se
+// devuelven como omitidos.
+pub fn cargar(
+    carpetas: &[PathBuf],
+    recursivo: bool,
+    seleccion: &Seleccion,
+    orden: Orden,
+) -> error::Result<(Vec<Frame>, Vec<Omitido>)> {
+    let mut archivos = Vec::new();
+    let mut visitadas = HashSet::new();
+    for carpeta in carpetas {
+        listar(carpeta, recursivo, &mut visitadas, &mut archivos)?;
+    }
+    // La misma carpeta puede llegar dos veces, directa o por recursión
+    archivos.sort();
+    archivos.dedup();
+
+    let mut frames = Vec::new();
+    let mut omitidos = Vec::new();
+    for path in archivos {
+        let motivo = match seleccion.rechazo(&path) {
+            Some(motivo) => Some(motivo.to_string()),
+            None => detectar_formato(&path).err(),
+        };
+        match motivo {
+            Some(motivo) => omitidos.push(Omitido { path, motivo }),
+            None => frames.push(Frame::new(path)),
+        }
+    }
+    orden::ordenar(&mut frames, orden);
+    Ok((frames, omitidos))
+}
+
+// Los enlaces simbólicos se siguen, pero cada carpeta se recorre una sola
+// vez: un enlace a una carpeta ya visitada (p. ej. a la carpeta padre) se
+// saltea y el recorrido siempre termina
+fn listar(
+    carpeta: &Path,
+    recursivo: bool,
+    visitadas: &mut HashSet<PathBuf>,
+    archivos: &mut Vec<PathBuf>,
+) -> error::Result<()> {
+    let real = fs::canonicalize(carpeta).map_err(Error::io(carpeta))?;
+    if !visitadas.insert(real) {
+        return Ok(());
+    }
+    for entrada in fs::read_dir(carpeta).map_err(Error::io(carpeta))? {
+        let path = entrada.map_err(Error::io(carpeta))?.path();
+        if path.is_dir() {
+            if recursivo {
+                listar(&path, recursivo, visitadas, archivos)?;
+            }
+        } else if path.is_file() {
+            archivos.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Qué se intercala entre el final de una sesión y el comienzo de la siguiente
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Separador {
+    // Se congela la última imagen de la sesión
+    Pausa,
+    // Cuadro negro
+    Negro,
+    // Cuadro con el nombre de la carpeta de la sesión siguiente
+    Titulo,
+}
+
+// Cada carpeta con imágenes es una sesión. Los frames se agrupan por sesión,
+// en el orden en que aparece el primero de cada una y sin cambiar el orden
+// dentro de cada sesión, así un frame que el orden deja entre los de otra
+// carpeta no la parte en dos. Cuando hay más de una sesión se marca el paso
+// entre ellas con el separador elegido, que dura `duracion` ms. Devuelve
+// también cuántas sesiones hay.
+pub fn separar_sesiones(frames: Vec<Frame>, separador: Separador, duracion: i64) -> (Vec<Frame>, usize) {
+    let total = frames.len();
+    let mut sesiones: Vec<(PathBuf, Vec<Frame>)> = Vec::new();
+    for frame in frames {
+        let carpeta = frame.path.parent().map(Path::to_path_buf).unwrap_or_default();
+        match sesiones.iter_mut().find(|(c, _)| *c == carpeta) {
+            Some((_, sesion)) => sesion.push(frame),
+            None => sesiones.push((carpeta, vec![frame])),
+        }
+    }
+    let cantidad = sesiones.len();
+    if cantidad < 2 {
+        return (sesiones.into_iter().flat_map(|(_, sesion)| sesion).collect(), cantidad);
+    }
+
+    let mut resultado: Vec<Frame> = Vec::with_capacity(total + sesiones.len());
+    for (numero, (carpeta, sesion)) in sesiones.into_iter().enumerate() {
+        let primera = numero == 0;
+        match separador {
+            Separador::Pausa => {
+                if let Some(ultimo) = resultado.last_mut() {
+                    ultimo.duracion = Some(duracion);
+                }
+            }
+            Separador::Negro if !primera => {
+                resultado.push(Frame::tarjeta(carpeta.clone(), String::new(), duracion));
+            }
+            Separador::Negro => {}
+            Separador::Titulo => {
+                let nombre = carpeta
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                resultado.push(Frame::tarjeta(carpeta.clone(), nombre, duracion));
+            }
+        }
+        resultado.extend(sesion);
+    }
+    (resultado, cantidad)
+}
+
+// Reconoce el formato por el contenido del archivo y no por la extensión.
+// Se crea el decodificador, que solo lee la cabecera: un formato puede
+// reconocerse sin que esta compilación lo sepa decodificar (p. ej. AVIF sin
+// dav1d), y esos archivos se omiten en lugar de fallar al codificar.
+pub fn detectar_formato(path: &Path) -> Result<image::ImageFormat, String> {
+    let reader = image::ImageReader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| format!("no se pudo leer: {}", e))?;
+    let formato = match reader.format() {
+        Some(formato) if formato.reading_enabled() => formato,
+        Some(formato) => return Err(format!("formato {:?} no soportado", formato)),
+        None => return Err("no es una imagen reconocida".to_string()),
+    };
+    match reader.into_decoder() {
+        Ok(_) => Ok(formato),
+        Err(image::ImageError::Unsupported(_)) => {
+            Err(format!("formato {:?} no soportado por esta compilación", formato))
+        }
+        Err(e) => Err(format!("no se pudo decodificar: {}", e)),
+    }
+}
+
+pub fn reportar_omitidos(omitidos: &[Omitido]) {
+    if omitidos.is_empty() {
+        return;
+    }
+    println!("Archivos omitidos ({}):", omitidos.len());
+    for omitido in omitidos {
+        println!("  {}: {}", omitido.path.display(), omitido.motivo);
+    }
+}
+
+// Muestra las pausas entre capturas consecutivas mayores al umbral
+pub fn reportar_huecos(frames: &[Frame], umbral_seg: u64) {
+    let umbral = umbral_seg as i64 * 1_000;
+    let con_fecha: Vec<_> = frames
+        .iter()
+        .filter_map(|f| f.capturado.map(|t| (f, t)))
+        .collect();
+
+    let sin_fecha = frames.len() - con_fecha.len();
+    if sin_fecha > 0 {
+        println!("{} imágenes sin fecha de captura EXIF", sin_fecha);
+    }
+
+    for par in con_fecha.windows(2) {
+        let ((anterior, t0), (siguiente, t1)) = (par[0], par[1]);
+        let diferencia = t1 - t0;
+        if diferencia < 0 {
+            println!(
+                "Captura fuera de orden: {} es anterior a {}",
+                siguiente.path.display(),
+                anterior.path.display()
+            );
+        } else if diferencia > umbral {
+            println!(
+                "Hueco de {} entre {} y {}",
+                formatear_duracion(diferencia),
+                anterior.path.display(),
+                siguiente.path.display()
+            );
+        }
+    }
+}
+
+pub fn formatear_duracion(ms: i64) -> String {
+    let seg = ms / 1_000;
+    let (h, m, s) = (seg / 3_600, (seg % 3_600) / 60, seg % 60);
+    if h > 0 {
+        format!("{}h {:02}m {:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {:02}s", m, s)
+    } else {
+        format!("{}.{:03}s", s, ms % 1_000)
+    }
+}
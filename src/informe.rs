@@ -0,0 +1,26 @@
+use std::fs::File;
+use std::path::Path;
+
+use crate::error::{self, Error};
+
+// CSV en el que un filtro anota lo que hizo con cada frame
+pub type Escritor = csv::Writer<File>;
+
+// Crea el CSV (o lo vacía si ya existe) y escribe la cabecera
+pub fn abrir_csv(path: &Path, cabecera: &[&str]) -> error::Result<Escritor> {
+    let archivo = File::create(path).map_err(Error::io(path))?;
+    let mut escritor = csv::Writer::from_writer(archivo);
+    escritor
+        .write_record(cabecera)
+        .map_err(|e| Error::Configuracion(format!("{}: {}", path.display(), e)))?;
+    Ok(escritor)
+}
+
+// Vuelve a empezar el informe de un filtro al comenzar una pasada. El
+// escritor anterior se cierra antes de volver a crear el archivo; sin
+// `path` el filtro queda sin informe.
+pub fn reabrir_csv(escritor: &mut Option<Escritor>, path: Option<&Path>, cabecera: &[&str]) -> error::Result<()> {
+    *escritor = None;
+    *escritor = path.map(|path| abrir_csv(path, cabecera)).transpose()?;
+    Ok(())
+}
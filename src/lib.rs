@@ -0,0 +1,28 @@
+// Biblioteca para armar videos timelapse a partir de fotos. El binario
+// `timelapse_lego` es una interfaz de línea de comandos sobre
+// `TimelapseBuilder`.
+
+pub mod antiparpadeo;
+pub mod balance;
+pub mod codec;
+pub mod conversion;
+pub mod error;
+pub mod estabilizacion;
+pub mod fallos;
+pub mod filtros;
+pub mod frames;
+pub mod informe;
+pub mod manifiesto;
+pub mod mediana;
+pub mod metadatos;
+pub mod oclusion;
+pub mod orden;
+pub mod precarga;
+pub mod seleccion;
+pub mod texto;
+pub mod tiempo;
+pub mod timelapse;
+
+pub use error::{Error, Etapa};
+pub use filtros::FrameFilter;
+pub use timelapse::{Progreso, Resumen, Timelapse, TimelapseBuilder};
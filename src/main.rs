@@ -1,154 +1,317 @@
 use std::path::PathBuf;
-use std::fs;
+use std::process::ExitCode;
 use clap::{Parser};
-use ffmpeg_next::{
-    format,
-    frame,
-    util::rational::Rational,
-    Packet,
-    Error,
-};
-use image::{GenericImageView};
-
-// Crea el video timelapse desde una carpeta con imagenes
+
+use timelapse_lego::codec::{self, Calidad, Codec, Tune};
+use timelapse_lego::conversion::{self, Conversor, Matriz, Rango};
+use timelapse_lego::fallos::{self, Politica};
+use timelapse_lego::filtros::{self, AjusteDimensiones, ModoAjuste, Resolucion};
+use timelapse_lego::frames::{self, Separador};
+use timelapse_lego::oclusion::{self, AccionOclusion};
+use timelapse_lego::orden::Orden;
+use timelapse_lego::tiempo::{self, Compresion, OpcionesVfr};
+use timelapse_lego::{Error, Progreso, Timelapse};
+
+// Crea el video timelapse desde una o varias carpetas con imagenes
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(required = true)]
-    carpeta: PathBuf,
+    #[arg(required_unless_present = "lista")]
+    carpeta: Vec<PathBuf>,
+
+    // Lista de frames (texto, .csv o .json) que reemplaza a las carpetas
+    #[arg(short, long, conflicts_with = "carpeta")]
+    lista: Option<PathBuf>,
+
+    // Incluye las imágenes de las subcarpetas
+    #[arg(short, long)]
+    recursivo: bool,
 
     #[arg(short, long, default_value_t = 10)]
     fps: u32,
 
     #[arg(short, long, default_value = "timelapse.mp4")]
     salida: String,
-}
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    ffmpeg_next::init()?;
-
-    // Leer rutas de imágenes
-    let mut paths: Vec<_> = fs::read_dir(&args.carpeta)?
-        .filter_map(Result::ok)
-        .map(|e| e.path())
-        .filter(|p| p.extension().map_or(false, |ext| ext == "jpg" || ext == "png"))
-        .collect();
-    paths.sort();
-
-    if paths.is_empty() {
-        println!("No se encontraron imágenes en la carpeta");
-        return Ok(());
-    }
+    // Códec de video; tiene que ser compatible con la extensión de la salida
+    #[arg(long, value_enum, default_value_t = Codec::H264)]
+    codec: Codec,
 
-    // Dimensiones de la primera imagen
-    let (width, height) = get_image_dimensions(&paths[0])?;
-    
-    // Crear contexto de salida
-    let mut octx = format::output(&args.salida)?;
-    let codec_id = ffmpeg_next::codec::Id::H264;
-    let stream = octx.add_stream(codec_id)?;
-    let mut encoder = stream.codec().encoder().video()?;
-
-    encoder.set_width(width);
-    encoder.set_height(height);
-    encoder.set_format(ffmpeg_next::format::Pixel::YUV420P);
-    encoder.set_time_base(Rational::new(1, args.fps as i32));
-    let mut encoder = encoder.open_as(codec_id)?;
-    octx.write_header()?;
-
-    // Procesar imágenes
-    for (i, path) in paths.iter().enumerate() {
-        println!("Procesando: {}", path.display());
-        let mut img = image::ImageReader::open(path)?.decode()?;
-
-        if img.width() != width || img.height() != height {
-            println!("  Redimensionando de {}x{} a {}x{}", img.width(), img.height(), width, height);
-            img = img.resize_exact(width, height, image::imageops::FilterType::Lanczos3);
-        }
+    // Matriz de color (por defecto BT.709 desde 720 líneas y BT.601 por debajo)
+    #[arg(long, value_enum)]
+    matriz: Option<Matriz>,
 
-        let img = img.to_rgb8();
-        // Convertir a frame RGB24
-        let mut f = frame::Video::new(ffmpeg_next::format::Pixel::YUV420P, width, height);
-        rgb_to_yuv420p(&img, &mut f, width, height);
+    // Rango de las muestras (por defecto limitado, completo con MJPEG)
+    #[arg(long, value_enum)]
+    rango: Option<Rango>,
 
-        f.set_pts(Some(i as i64));
-        encoder.send_frame(&f)?;
+    // Conversión de RGB al formato del encoder
+    #[arg(long, value_enum, default_value_t = Conversor::Swscale)]
+    conversor: Conversor,
 
-        receive_and_write_packets(&mut encoder, &mut octx)?;
-    }
+    // Formato de pixel del encoder, p. ej. yuv444p, nv12 o yuv420p10le (por
+    // defecto el habitual del códec)
+    #[arg(long, value_parser = conversion::parse_pixel)]
+    formato_pixel: Option<ffmpeg_next::format::Pixel>,
 
-    // Vaciar encoder
-    encoder.send_eof()?;
-    receive_and_write_packets(&mut encoder, &mut octx)?;
+    // Resolución del video: 1920x1080, 1280 (alto automático), x720, 1080p o 4k
+    // (por defecto la de la primera imagen). Las imágenes se ajustan antes de
+    // los --filtro, así que un filtro que cambia el tamaño cambia el del video.
+    #[arg(long)]
+    resolucion: Option<Resolucion>,
 
-    octx.write_trailer()?;
-    println!("\nVideo timelapse guardado como '{}' exitosamente", args.salida);
-    Ok(())
-}
+    // No gira las fotos según la orientación EXIF
+    #[arg(long)]
+    ignorar_orientacion: bool,
+
+    // Cómo entran las imágenes de otra proporción: encajar (con bandas),
+    // llenar (recortando) o estirar
+    #[arg(long, value_enum, default_value_t = ModoAjuste::Encajar)]
+    modo_ajuste: ModoAjuste,
+
+    // Color de las bandas al encajar: #rrggbb, r,g,b, negro, blanco o gris
+    #[arg(long, default_value = "negro", value_parser = filtros::parse_color)]
+    color_relleno: image::Rgb<u8>,
+
+    // Qué hacer si la resolución no sirve para el formato de pixel (p. ej.
+    // impar en 4:2:0)
+    #[arg(long, value_enum, default_value_t = AjusteDimensiones::Recortar)]
+    ajuste_dimensiones: AjusteDimensiones,
+
+    // Lleva el ancho y el alto a múltiplos de 16
+    #[arg(long)]
+    multiplo_16: bool,
+
+    // Calidad constante (0-51 en x264/x265, 0-63 en VP9/AV1; más bajo es mejor)
+    #[arg(long)]
+    crf: Option<u32>,
+
+    // Tasa de bits objetivo, p. ej. 8M o 800k
+    #[arg(long, value_parser = codec::parse_bitrate)]
+    bitrate: Option<u64>,
+
+    // Tasa de bits máxima; requiere --bufsize
+    #[arg(long, value_parser = codec::parse_bitrate)]
+    maxrate: Option<u64>,
+
+    // Tamaño del buffer del decodificador para --maxrate
+    #[arg(long, value_parser = codec::parse_bitrate)]
+    bufsize: Option<u64>,
+
+    // Velocidad del encoder: ultrafast...veryslow en x264/x265, 0-13 en SVT-AV1,
+    // cpu-used en VP9/libaom
+    #[arg(long)]
+    preset: Option<String>,
+
+    // Ajuste para el tipo de contenido (x264/x265)
+    #[arg(long, value_enum)]
+    tune: Option<Tune>,
+
+    // Perfil del códec, p. ej. high, main10 o hq para ProRes
+    #[arg(long)]
+    perfil: Option<String>,
+
+    // Nivel del códec, p. ej. 4.1
+    #[arg(long)]
+    nivel: Option<String>,
+
+    // Distancia máxima entre keyframes, en frames
+    #[arg(long)]
+    gop: Option<u32>,
+
+    // Tamaño del video en MB (1 MB = 1.000.000 bytes); codifica en dos pasadas
+    #[arg(long, conflicts_with_all = ["crf", "bitrate"], value_parser = codec::parse_megabytes)]
+    tamano_objetivo: Option<u64>,
+
+    // Hilos que leen y filtran imágenes por adelantado (por defecto uno por núcleo)
+    #[arg(long)]
+    hilos: Option<usize>,
+
+    // Memoria en MB para las imágenes leídas que esperan al encoder
+    #[arg(long, default_value = "1000", value_parser = codec::parse_megabytes)]
+    memoria_precarga: u64,
+
+    // Criterio para ordenar las imágenes
+    #[arg(long, value_enum, default_value_t = Orden::Natural)]
+    orden: Orden,
+
+    // Solo usa los archivos cuyo nombre coincide con alguno de estos patrones
+    #[arg(long)]
+    incluir: Vec<String>,
+
+    // Descarta los archivos cuyo nombre coincide con alguno de estos patrones
+    #[arg(long)]
+    excluir: Vec<String>,
+
+    // Qué se intercala entre sesiones (cada carpeta con imágenes es una sesión)
+    #[arg(long, value_enum)]
+    separador: Option<Separador>,
 
-fn get_image_dimensions(path: &PathBuf) -> Result<(u32, u32), image::ImageError> {
-    let img = image::ImageReader::open(path)?.decode()?;
-    Ok(img.dimensions())
+    // Duración del separador entre sesiones
+    #[arg(long, default_value = "1s", value_parser = tiempo::parse_duracion)]
+    separacion: i64,
+
+    // Pausas entre capturas (en segundos) a partir de las cuales se avisa
+    #[arg(long, default_value_t = 600)]
+    hueco: u64,
+
+    // Cada imagen dura lo que tardó la siguiente captura (frame rate variable)
+    #[arg(long)]
+    tiempo_real: bool,
+
+    // Tiempo real equivalente a tiempo de video con --tiempo-real, p. ej. 1h=2s
+    #[arg(long, default_value = "1h=2s")]
+    compresion: Compresion,
+
+    // Duración mínima de cada imagen con --tiempo-real (por defecto 1/fps)
+    #[arg(long, value_parser = tiempo::parse_duracion)]
+    duracion_min: Option<i64>,
+
+    // Duración máxima de cada imagen con --tiempo-real
+    #[arg(long, value_parser = tiempo::parse_duracion)]
+    duracion_max: Option<i64>,
+
+    // Busca frames tapados (p. ej. por las manos) comparando cada uno con sus
+    // vecinos, y los descarta o los reemplaza por el último frame limpio
+    #[arg(long, value_enum)]
+    oclusion: Option<AccionOclusion>,
+
+    // Porcentaje de la imagen que tiene que cambiar solo en un frame para
+    // considerarlo tapado
+    #[arg(long, default_value_t = 5.0)]
+    umbral_oclusion: f64,
+
+    // Qué hacer con las imágenes que no se pueden leer
+    #[arg(long, value_enum, default_value_t = Politica::Abort)]
+    on_error: Politica,
+
+    // Filtro a aplicar a cada imagen, p. ej. rotar=90 o redimensionar=1280x720;
+    // se puede repetir y se aplican en orden
+    #[arg(long)]
+    filtro: Vec<String>,
+
+    // Archivo con un filtro por línea; van antes que los de --filtro
+    #[arg(long)]
+    filtros: Option<PathBuf>,
 }
 
-fn receive_and_write_packets(
-    encoder: &mut ffmpeg_next::codec::encoder::Video,
-    octx: &mut format::context::Output
-) -> Result<(), Error> {
-    let mut packet = Packet::empty();
-    loop {
-        match encoder.receive_packet(&mut packet) {
-            Ok(_) => {
-                packet.write_interleaved(octx)?;
-            }
-            Err(Error::Other { errno }) if errno == 11 || errno == ffmpeg_next::sys::AVERROR(ffmpeg_next::sys::EAGAIN) => {
-                // El encoder necesita más frames antes de producir paquetes
-                break;
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let politica = args.on_error;
+    let salida = args.salida.clone();
+
+    match armar(args).and_then(Timelapse::ejecutar) {
+        Ok(resumen) => {
+            fallos::reportar(&resumen.fallos, politica);
+            println!("\nDuración del video: {}", frames::formatear_duracion(resumen.duracion_ms));
+            println!("Video timelapse guardado como '{}' exitosamente", salida);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            if let Error::Interrumpido { fallos, .. } = &e {
+                fallos::reportar(fallos, politica);
             }
-            Err(Error::Eof) => break,
-            Err(e) => return Err(e),
+            eprintln!("Error: {}", e);
+            ExitCode::from(e.codigo_salida())
         }
     }
-    Ok(())
 }
 
-fn rgb_to_yuv420p(rgb: &image::RgbImage, frame: &mut frame::Video, width: u32, height: u32) {
-    let w = width as usize;
-    let h = height as usize;
-    
-    // Obtener los strides primero
-    let y_stride = frame.stride(0);
-    let u_stride = frame.stride(1);
-    let v_stride = frame.stride(2);
-    
-    // Convertir RGB a YUV calcula todos los valores
-    let mut y_values = vec![0u8; h * y_stride];
-    let mut u_values = vec![0u8; (h / 2) * u_stride];
-    let mut v_values = vec![0u8; (h / 2) * v_stride];
-    
-    for y in 0..h {
-        for x in 0..w {
-            let pixel = rgb.get_pixel(x as u32, y as u32);
-            let r = pixel[0] as f32;
-            let g = pixel[1] as f32;
-            let b = pixel[2] as f32;
-            
-            // Conversión RGB -> YUV (BT.601)
-            let y_val = (0.257 * r + 0.504 * g + 0.098 * b + 16.0) as u8;
-            y_values[y * y_stride + x] = y_val;
-            
-            // Submuestreo para U y V (cada 2x2 pixels)
-            if y % 2 == 0 && x % 2 == 0 {
-                let u_val = (-0.148 * r - 0.291 * g + 0.439 * b + 128.0) as u8;
-                let v_val = (0.439 * r - 0.368 * g - 0.071 * b + 128.0) as u8;
-                
-                u_values[(y / 2) * u_stride + (x / 2)] = u_val;
-                v_values[(y / 2) * v_stride + (x / 2)] = v_val;
+// Traduce los argumentos a la configuración de la biblioteca
+fn armar(args: Args) -> timelapse_lego::error::Result<Timelapse> {
+    let hueco = args.hueco;
+    let mut builder = Timelapse::builder()
+        .carpetas(args.carpeta)
+        .recursivo(args.recursivo)
+        .orden(args.orden)
+        .fps(args.fps)
+        .salida(args.salida)
+        .codec(args.codec)
+        .conversor(args.conversor)
+        .orientacion_exif(!args.ignorar_orientacion)
+        .modo_ajuste(args.modo_ajuste)
+        .color_relleno(args.color_relleno)
+        .ajuste_dimensiones(args.ajuste_dimensiones)
+        .multiplo_16(args.multiplo_16)
+        .memoria_precarga(args.memoria_precarga as usize)
+        .calidad(Calidad {
+            crf: args.crf,
+            bitrate: args.bitrate,
+            maxrate: args.maxrate,
+            bufsize: args.bufsize,
+            preset: args.preset,
+            tune: args.tune,
+            perfil: args.perfil,
+            nivel: args.nivel,
+            gop: args.gop,
+        })
+        .politica(args.on_error)
+        .progreso(move |progreso| match progreso {
+            Progreso::Cargado { frames, omitidos } => {
+                frames::reportar_omitidos(omitidos);
+                frames::reportar_huecos(frames, hueco);
             }
-        }
+            Progreso::Frame { frame, .. } if frame.titulo.is_some() => {
+                println!("Separador de sesión: {}", frame.path.display());
+            }
+            Progreso::Frame { frame, .. } => println!("Procesando: {}", frame.path.display()),
+            Progreso::Fallo { error, .. } => println!("  Error: {}", error),
+            Progreso::Sesiones { cantidad } => println!("{} sesiones en la línea de tiempo", cantidad),
+            Progreso::Oclusion { ocluidos, accion } => oclusion::reportar(ocluidos, accion),
+            Progreso::Pasada { numero, total } => println!("\nPasada {} de {}", numero, total),
+            Progreso::Ajuste { original, ajustada, modo } => println!(
+                "Dimensiones {}x{} ajustadas a {}x{} ({:?})",
+                original.0, original.1, ajustada.0, ajustada.1, modo
+            ),
+        });
+    if let Some(lista) = args.lista {
+        builder = builder.lista(lista);
+    }
+    for patron in args.incluir {
+        builder = builder.incluir(patron);
+    }
+    for patron in args.excluir {
+        builder = builder.excluir(patron);
+    }
+    if let Some(matriz) = args.matriz {
+        builder = builder.matriz(matriz);
+    }
+    if let Some(pixel) = args.formato_pixel {
+        builder = builder.formato_pixel(pixel);
+    }
+    if let Some(rango) = args.rango {
+        builder = builder.rango(rango);
+    }
+    if let Some(resolucion) = args.resolucion {
+        builder = builder.resolucion(resolucion);
+    }
+    if let Some(accion) = args.oclusion {
+        builder = builder.oclusion(accion, args.umbral_oclusion / 100.0);
     }
-    frame.data_mut(0)[..y_values.len()].copy_from_slice(&y_values);
-    frame.data_mut(1)[..u_values.len()].copy_from_slice(&u_values);
-    frame.data_mut(2)[..v_values.len()].copy_from_slice(&v_values);
-}
\ No newline at end of file
+    if let Some(hilos) = args.hilos {
+        builder = builder.hilos(hilos);
+    }
+    if let Some(bytes) = args.tamano_objetivo {
+        builder = builder.tamano_objetivo(bytes);
+    }
+    if let Some(separador) = args.separador {
+        builder = builder.separador(separador, args.separacion);
+    }
+    if args.tiempo_real {
+        builder = builder.tiempo_real(OpcionesVfr {
+            compresion: args.compresion,
+            // Sin mínimo explícito se usa 1/fps, sin pasarse de la máxima
+            duracion_min: args
+                .duracion_min
+                .unwrap_or((1_000 / args.fps.max(1) as i64).min(args.duracion_max.unwrap_or(i64::MAX))),
+            duracion_max: args.duracion_max,
+        });
+    }
+    if let Some(archivo) = args.filtros {
+        builder = builder.filtros(filtros::leer_archivo(&archivo)?);
+    }
+    for descripcion in &args.filtro {
+        builder = builder.filtros([filtros::crear(descripcion)?]);
+    }
+    builder.build()
+}
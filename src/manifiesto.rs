@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{self, Error};
+use crate::frames::{self, Frame, Omitido, Recorte};
+use crate::tiempo;
+
+// Una línea del manifiesto antes de resolver la ruta y leer la imagen
+struct Entrada {
+    ruta: String,
+    duracion: Option<i64>,
+    leyenda: Option<String>,
+    recorte: Option<Recorte>,
+}
+
+// Lee una lista de frames en el orden en que aparecen. El formato se elige
+// por la extensión: .json, .csv o texto con una imagen por línea. Las rutas
+// relativas parten de la carpeta del manifiesto.
+pub fn cargar(path: &Path) -> error::Result<(Vec<Frame>, Vec<Omitido>)> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let entradas = match extension.as_str() {
+        "json" => leer_json(path)?,
+        "csv" => leer_csv(path)?,
+        _ => leer_texto(path)?,
+    };
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut frames_lista = Vec::with_capacity(entradas.len());
+    let mut omitidos = Vec::new();
+    for entrada in entradas {
+        let ruta = base.join(PathBuf::from(&entrada.ruta));
+        if let Err(motivo) = frames::detectar_formato(&ruta) {
+            omitidos.push(Omitido { path: ruta, motivo });
+            continue;
+        }
+        let mut frame = Frame::new(ruta);
+        frame.duracion = entrada.duracion;
+        frame.leyenda = entrada.leyenda;
+        frame.recorte = entrada.recorte;
+        frames_lista.push(frame);
+    }
+    Ok((frames_lista, omitidos))
+}
+
+// Texto: "ruta | duración | leyenda | recorte", solo la ruta es obligatoria.
+// Las líneas vacías y las que empiezan con # se ignoran.
+fn leer_texto(path: &Path) -> error::Result<Vec<Entrada>> {
+    let contenido = fs::read_to_string(path).map_err(Error::io(path))?;
+    let mut entradas = Vec::new();
+    for (i, linea) in contenido.lines().enumerate() {
+        let linea = linea.trim();
+        if linea.is_empty() || linea.starts_with('#') {
+            continue;
+        }
+        let campos: Vec<&str> = linea.split('|').map(str::trim).collect();
+        let entrada = entrada(&campos).map_err(|e| invalido(path, i + 1, e))?;
+        entradas.push(entrada);
+    }
+    Ok(entradas)
+}
+
+// CSV con encabezado; las columnas se buscan por nombre
+fn leer_csv(path: &Path) -> error::Result<Vec<Entrada>> {
+    let error_csv = |e: csv::Error| Error::Configuracion(format!("{}: {}", path.display(), e));
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(path)
+        .map_err(error_csv)?;
+    let encabezado = reader.headers().map_err(error_csv)?.clone();
+    let columna = |nombre: &str| encabezado.iter().position(|h| h.eq_ignore_ascii_case(nombre));
+    let indices = [
+        columna("ruta").ok_or_else(|| invalido(path, 1, "falta la columna 'ruta'".to_string()))?,
+        columna("duracion").unwrap_or(usize::MAX),
+        columna("leyenda").unwrap_or(usize::MAX),
+        columna("recorte").unwrap_or(usize::MAX),
+    ];
+
+    let mut entradas = Vec::new();
+    for (i, registro) in reader.records().enumerate() {
+        let registro = registro.map_err(error_csv)?;
+        let campos: Vec<&str> = indices.iter().map(|&c| registro.get(c).unwrap_or("")).collect();
+        // La línea 1 es el encabezado
+        let entrada = entrada(&campos).map_err(|e| invalido(path, i + 2, e))?;
+        entradas.push(entrada);
+    }
+    Ok(entradas)
+}
+
+// JSON: una lista de rutas o de objetos con "ruta" y los campos opcionales
+// "duracion" (texto con unidad o número en segundos), "leyenda" y "recorte"
+fn leer_json(path: &Path) -> error::Result<Vec<Entrada>> {
+    let contenido = fs::read_to_string(path).map_err(Error::io(path))?;
+    let valor: serde_json::Value = serde_json::from_str(&contenido)
+        .map_err(|e| Error::Configuracion(format!("{}: {}", path.display(), e)))?;
+    let lista = valor.as_array().ok_or_else(|| {
+        Error::Configuracion(format!("{}: se esperaba una lista de frames", path.display()))
+    })?;
+
+    let mut entradas = Vec::new();
+    for (i, item) in lista.iter().enumerate() {
+        let error = |e: String| Error::Configuracion(format!("{}: frame {}: {}", path.display(), i, e));
+        let entrada = match item {
+            serde_json::Value::String(ruta) => entrada(&[ruta.as_str()]).map_err(error)?,
+            serde_json::Value::Object(campos) => {
+                let texto = |nombre: &str| campos.get(nombre).and_then(|v| v.as_str()).unwrap_or("");
+                let duracion = match campos.get("duracion") {
+                    Some(serde_json::Value::Number(n)) => format!("{}s", n),
+                    _ => texto("duracion").to_string(),
+                };
+                entrada(&[texto("ruta"), &duracion, texto("leyenda"), texto("recorte")]).map_err(error)?
+            }
+            _ => return Err(error("se esperaba una ruta o un objeto".to_string())),
+        };
+        entradas.push(entrada);
+    }
+    Ok(entradas)
+}
+
+fn invalido(path: &Path, linea: usize, mensaje: String) -> Error {
+    Error::Configuracion(format!("{}:{}: {}", path.display(), linea, mensaje))
+}
+
+// Arma una entrada desde los campos en orden; los vacíos se toman como ausentes
+fn entrada(campos: &[&str]) -> Result<Entrada, String> {
+    let campo = |i: usize| campos.get(i).copied().filter(|c| !c.is_empty());
+    let ruta = campo(0).ok_or("falta la ruta de la imagen")?.to_string();
+    Ok(Entrada {
+        ruta,
+        duracion: campo(1).map(tiempo::parse_duracion).transpose()?,
+        leyenda: campo(2).map(str::to_string),
+        recorte: campo(3).map(str::parse).transpose()?,
+    })
+}
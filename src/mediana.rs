@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use image::RgbImage;
+use rayon::prelude::*;
+
+use crate::error;
+use crate::filtros::{Contexto, FrameFilter};
+
+// Borra lo que aparece por poco tiempo, como manos o gatos: cada frame
+// sale como la mediana pixel por pixel de una ventana de `ventana` frames
+// centrada en él. Lo que se agrega a la construcción aparece cuando está en
+// más de la mitad de la ventana. El filtro devuelve cada frame recién cuando
+// recibió los ventana/2 siguientes; al principio y al final la ventana
+// queda recortada.
+pub struct Mediana {
+    ventana: usize,
+    // Bytes que pueden ocupar los frames guardados; con imágenes grandes la
+    // ventana se achica para no pasarse
+    memoria: usize,
+    frames: VecDeque<RgbImage>,
+    // Cuántos de los últimos frames guardados todavía no salieron
+    pendientes: usize,
+}
+
+impl Mediana {
+    pub fn new(ventana: usize, memoria: usize) -> Self {
+        Mediana {
+            ventana: ventana.max(1),
+            memoria,
+            frames: VecDeque::new(),
+            pendientes: 0,
+        }
+    }
+
+    // Frames de la ventana antes y después del central para imágenes de
+    // `bytes`, según la memoria disponible
+    fn lados(&self, bytes: usize) -> (usize, usize) {
+        let ventana = (self.memoria / bytes.max(1)).clamp(1, self.ventana);
+        let despues = ventana / 2;
+        (ventana - 1 - despues, despues)
+    }
+
+    // Mediana del primer frame pendiente con los que tiene alrededor
+    fn siguiente(&mut self, (antes, despues): (usize, usize)) -> RgbImage {
+        let posicion = self.frames.len() - self.pendientes;
+        self.pendientes -= 1;
+        let desde = posicion.saturating_sub(antes);
+        let hasta = (posicion + despues + 1).min(self.frames.len());
+        if hasta - desde == 1 {
+            return self.frames[posicion].clone();
+        }
+        let fuentes: Vec<&[u8]> = self.frames.range(desde..hasta).map(|f| f.as_raw().as_slice()).collect();
+        mediana(self.frames[posicion].dimensions(), &fuentes)
+    }
+
+    // Devuelve todos los pendientes y deja la ventana vacía
+    fn vaciar(&mut self) -> Vec<RgbImage> {
+        let Some(bytes) = self.frames.front().map(|f| f.as_raw().len()) else {
+            return Vec::new();
+        };
+        let lados = self.lados(bytes);
+        let salida = (0..self.pendientes).map(|_| self.siguiente(lados)).collect();
+        self.frames.clear();
+        salida
+    }
+}
+
+// Parámetros separados por comas: "ventana=5" (frames; mejor impar) y
+// "memoria=1000" (MB)
+impl FromStr for Mediana {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ventana = 5;
+        let mut memoria = 1_000;
+        for parametro in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let invalido = || format!("parámetro inválido: '{}'", parametro);
+            match parametro.split_once('=') {
+                Some(("ventana", valor)) => {
+                    ventana = valor.trim().parse().ok().filter(|&v| v > 0).ok_or_else(invalido)?;
+                }
+                Some(("memoria", valor)) => {
+                    memoria = valor.trim().parse().ok().filter(|&m| m > 0).ok_or_else(invalido)?;
+                }
+                _ => return Err(format!("parámetro desconocido: '{}'", parametro)),
+            }
+        }
+        Ok(Mediana::new(ventana, memoria * 1_000_000))
+    }
+}
+
+impl FrameFilter for Mediana {
+    fn nombre(&self) -> &str {
+        "mediana"
+    }
+
+    fn reiniciar(&mut self) -> error::Result<()> {
+        self.frames.clear();
+        self.pendientes = 0;
+        Ok(())
+    }
+
+    // Los frames salen con retraso por `recibir`; la cadena nunca llama acá
+    // a un filtro que no se puede duplicar
+    fn aplicar(&mut self, _img: RgbImage, ctx: &Contexto<'_>) -> error::Result<RgbImage> {
+        Err(ctx.error("mediana", "el filtro devuelve los frames con retraso"))
+    }
+
+    fn recibir(&mut self, img: RgbImage, _ctx: &Contexto<'_>) -> error::Result<Vec<RgbImage>> {
+        // Un frame de otro tamaño no se puede combinar con los anteriores
+        let mut salida = match self.frames.front() {
+            Some(f) if f.dimensions() != img.dimensions() => self.vaciar(),
+            _ => Vec::new(),
+        };
+        let (antes, despues) = self.lados(img.as_raw().len());
+        self.frames.push_back(img);
+        self.pendientes += 1;
+        while self.pendientes > despues {
+            salida.push(self.siguiente((antes, despues)));
+        }
+        // Solo se guardan los que ya salieron y todavía entran en la ventana
+        // de los pendientes
+        while self.frames.len() - self.pendientes > antes {
+            self.frames.pop_front();
+        }
+        Ok(salida)
+    }
+
+    fn terminar(&mut self) -> error::Result<Vec<RgbImage>> {
+        Ok(self.vaciar())
+    }
+}
+
+// Mediana pixel por pixel de imágenes del mismo tamaño
+fn mediana((width, height): (u32, u32), fuentes: &[&[u8]]) -> RgbImage {
+    let largo_fila = width as usize * 3;
+    let mut salida = RgbImage::new(width, height);
+    salida
+        .par_chunks_mut(largo_fila)
+        .enumerate()
+        .for_each(|(y, fila)| {
+            let inicio = y * largo_fila;
+            let mut valores = vec![0u8; fuentes.len()];
+            for (x, muestra) in fila.iter_mut().enumerate() {
+                for (valor, fuente) in valores.iter_mut().zip(fuentes) {
+                    *valor = fuente[inicio + x];
+                }
+                let mitad = valores.len() / 2;
+                *muestra = *valores.select_nth_unstable(mitad).1;
+            }
+        });
+    salida
+}
@@ -0,0 +1,68 @@
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+
+// Datos EXIF que usa el timelapse
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Metadatos {
+    // Momento de captura en milisegundos desde 1970 (DateTimeOriginal)
+    pub capturado: Option<i64>,
+}
+
+// Lee los metadatos de una imagen. Si no tiene EXIF o no se puede leer
+// se devuelven los valores por defecto en lugar de un error.
+pub fn leer(path: &Path) -> Metadatos {
+    let Ok(archivo) = fs::File::open(path) else {
+        return Metadatos::default();
+    };
+    let Ok(datos) = exif::Reader::new().read_from_container(&mut BufReader::new(archivo)) else {
+        return Metadatos::default();
+    };
+    Metadatos {
+        capturado: fecha_captura(&datos),
+    }
+}
+
+fn fecha_captura(datos: &exif::Exif) -> Option<i64> {
+    let mut fecha = exif::DateTime::from_ascii(ascii(datos, exif::Tag::DateTimeOriginal)?).ok()?;
+    if let Some(subseg) = ascii(datos, exif::Tag::SubSecTimeOriginal) {
+        let _ = fecha.parse_subsec(subseg);
+    }
+    if let Some(zona) = ascii(datos, exif::Tag::OffsetTimeOriginal) {
+        let _ = fecha.parse_offset(zona);
+    }
+    a_milisegundos(&fecha)
+}
+
+fn ascii(datos: &exif::Exif, tag: exif::Tag) -> Option<&[u8]> {
+    match datos.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Ascii(ref valores) => valores.first().map(|v| v.as_slice()),
+        _ => None,
+    }
+}
+
+// Sin zona horaria la fecha se toma como UTC; solo importan las diferencias
+fn a_milisegundos(fecha: &exif::DateTime) -> Option<i64> {
+    if !(1..=12).contains(&fecha.month) || !(1..=31).contains(&fecha.day) {
+        return None;
+    }
+    let dias = dias_desde_epoch(fecha.year as i64, fecha.month as i64, fecha.day as i64);
+    let mut segundos =
+        dias * 86_400 + fecha.hour as i64 * 3_600 + fecha.minute as i64 * 60 + fecha.second as i64;
+    if let Some(offset) = fecha.offset {
+        segundos -= offset as i64 * 60;
+    }
+    let ms = fecha.nanosecond.map_or(0, |ns| ns as i64 / 1_000_000);
+    Some(segundos * 1_000 + ms)
+}
+
+// Días entre 1970-01-01 y la fecha dada (calendario gregoriano)
+fn dias_desde_epoch(anio: i64, mes: i64, dia: i64) -> i64 {
+    let anio = if mes <= 2 { anio - 1 } else { anio };
+    let era = anio.div_euclid(400);
+    let anio_era = anio - era * 400;
+    let mes_marzo = (mes + 9) % 12;
+    let dia_anio = (153 * mes_marzo + 2) / 5 + dia - 1;
+    let dia_era = anio_era * 365 + anio_era / 4 - anio_era / 100 + dia_anio;
+    era * 146_097 + dia_era - 719_468
+}
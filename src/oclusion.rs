@@ -0,0 +1,178 @@
+use std::path::PathBuf;
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use image::DynamicImage;
+
+use crate::frames::Frame;
+
+// Tamaño de las miniaturas en las que se comparan los frames
+const ANCHO: u32 = 256;
+const ALTO: u32 = 192;
+// Diferencia de gris a partir de la cual un pixel cambió
+const DIFERENCIA_PIXEL: f32 = 25.0;
+
+// Qué hacer con los frames tapados, p. ej. por las manos del que arma
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccionOclusion {
+    // Se sacan del video
+    Descartar,
+    // Se muestra en su lugar el último frame limpio
+    Reemplazar,
+}
+
+// Frame que se detectó tapado
+#[derive(Debug, Clone)]
+pub struct Ocluido {
+    pub indice: usize,
+    pub path: PathBuf,
+    // Fracción de la imagen que cambió solo en este frame
+    pub fraccion: f64,
+}
+
+// Miniatura en gris sin el brillo medio, así un cambio de exposición no
+// cuenta como diferencia
+pub fn miniatura(img: &DynamicImage) -> Vec<f32> {
+    let gris = imageops::resize(&img.to_luma8(), ANCHO, ALTO, FilterType::Triangle);
+    let media = gris.iter().map(|&v| v as f32).sum::<f32>() / gris.len() as f32;
+    gris.iter().map(|&v| v as f32 - media).collect()
+}
+
+// Busca los frames con una zona grande que no está ni en el frame limpio
+// anterior ni en el siguiente, mientras esos dos coinciden entre sí: algo
+// que apareció y se fue, como una mano. `umbral` es la fracción de la
+// imagen a partir de la cual se marca. El primer y el último frame no
+// tienen con qué compararse y nunca se marcan; los que no tienen miniatura
+// (títulos o imágenes que no se pudieron leer) se saltean.
+pub fn detectar(frames: &[Frame], miniaturas: &[Option<Vec<f32>>], umbral: f64) -> Vec<Ocluido> {
+    let validos: Vec<usize> = (0..miniaturas.len()).filter(|&i| miniaturas[i].is_some()).collect();
+    let mut ocluidos = Vec::new();
+    let Some(&primero) = validos.first() else {
+        return ocluidos;
+    };
+
+    let mut limpio = primero;
+    for par in validos[1..].windows(2) {
+        let (actual, siguiente) = (par[0], par[1]);
+        let [Some(anterior), Some(imagen), Some(proxima)] =
+            [limpio, actual, siguiente].map(|i| miniaturas[i].as_ref())
+        else {
+            continue;
+        };
+        let cambiados = imagen
+            .iter()
+            .zip(anterior)
+            .zip(proxima)
+            .filter(|&((&a, &p), &n)| {
+                (a - p).abs() > DIFERENCIA_PIXEL && (a - n).abs() > DIFERENCIA_PIXEL && (p - n).abs() <= DIFERENCIA_PIXEL
+            })
+            .count();
+        let fraccion = cambiados as f64 / imagen.len() as f64;
+        if fraccion > umbral {
+            ocluidos.push(Ocluido {
+                indice: actual,
+                path: frames[actual].path.clone(),
+                fraccion,
+            });
+        } else {
+            limpio = actual;
+        }
+    }
+    ocluidos
+}
+
+// Saca los frames tapados o los reemplaza por el último limpio. El
+// reemplazo conserva la duración y la leyenda del frame original.
+pub fn aplicar(frames: Vec<Frame>, ocluidos: &[Ocluido], accion: AccionOclusion) -> Vec<Frame> {
+    let mut marcados = ocluidos.iter().map(|o| o.indice).peekable();
+    let mut limpio: Option<Frame> = None;
+    let mut resultado = Vec::with_capacity(frames.len());
+    for (i, mut frame) in frames.into_iter().enumerate() {
+        if marcados.next_if_eq(&i).is_none() {
+            if frame.titulo.is_none() {
+                limpio = Some(frame.clone());
+            }
+            resultado.push(frame);
+            continue;
+        }
+        if let (AccionOclusion::Reemplazar, Some(limpio)) = (accion, &limpio) {
+            frame.path = limpio.path.clone();
+            frame.recorte = limpio.recorte;
+            resultado.push(frame);
+        }
+    }
+    resultado
+}
+
+pub fn reportar(ocluidos: &[Ocluido], accion: AccionOclusion) {
+    if ocluidos.is_empty() {
+        return;
+    }
+    let accion = match accion {
+        AccionOclusion::Descartar => "se descartaron",
+        AccionOclusion::Reemplazar => "se reemplazaron por el último frame limpio",
+    };
+    println!("Frames tapados ({}, {}):", ocluidos.len(), accion);
+    for ocluido in ocluidos {
+        println!(
+            "  #{} {} ({:.1}% de la imagen)",
+            ocluido.indice + 1,
+            ocluido.path.display(),
+            ocluido.fraccion * 100.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    // Escena de fondo y la misma con una mano: un rectángulo oscuro que
+    // tapa más o menos un 12% de la imagen
+    fn escena(tapada: bool) -> Option<Vec<f32>> {
+        let img = RgbImage::from_fn(320, 240, |x, y| {
+            if tapada && (100..200).contains(&x) && (60..150).contains(&y) {
+                Rgb([30, 20, 20])
+            } else {
+                Rgb([(x / 2) as u8, (y / 2) as u8, 128])
+            }
+        });
+        Some(miniatura(&DynamicImage::ImageRgb8(img)))
+    }
+
+    fn frames(cantidad: usize) -> Vec<Frame> {
+        (0..cantidad).map(|i| Frame::new(PathBuf::from(format!("{:04}.jpg", i)))).collect()
+    }
+
+    fn indices(ocluidos: &[Ocluido]) -> Vec<usize> {
+        ocluidos.iter().map(|o| o.indice).collect()
+    }
+
+    #[test]
+    fn marca_lo_que_aparece_en_un_solo_frame() {
+        let miniaturas = [false, false, true, false, false].map(escena);
+        let ocluidos = detectar(&frames(5), &miniaturas, 0.05);
+        assert_eq!(indices(&ocluidos), [2]);
+        assert_eq!(ocluidos[0].path, PathBuf::from("0002.jpg"));
+        assert!(ocluidos[0].fraccion > 0.05);
+    }
+
+    #[test]
+    fn no_marca_un_cambio_que_se_queda() {
+        // Una pieza nueva sigue en los frames siguientes
+        let miniaturas = [false, false, true, true, true].map(escena);
+        assert!(detectar(&frames(5), &miniaturas, 0.05).is_empty());
+    }
+
+    #[test]
+    fn saltea_los_extremos_y_los_frames_sin_miniatura() {
+        // El primero y el último no tienen con qué compararse. El frame 4 se
+        // compara con el 2 porque el 3 no se pudo leer.
+        let mut miniaturas = [true, false, false, false, true, false, false, true].map(escena);
+        miniaturas[3] = None;
+        assert_eq!(indices(&detectar(&frames(8), &miniaturas, 0.05)), [4]);
+
+        assert!(detectar(&frames(1), &[escena(true)], 0.05).is_empty());
+        assert!(detectar(&frames(3), &[None, None, None], 0.05).is_empty());
+    }
+}
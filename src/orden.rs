@@ -0,0 +1,158 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+use clap::ValueEnum;
+
+use crate::frames::Frame;
+
+// Criterio con el que se ordenan los frames de entrada
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Orden {
+    // Orden alfabético de la ruta (IMG_10 va antes que IMG_2)
+    Lexico,
+    // Los números dentro del nombre se comparan por su valor
+    #[default]
+    Natural,
+    // Fecha de modificación del archivo
+    Mtime,
+    // Fecha de captura EXIF (DateTimeOriginal)
+    Exif,
+}
+
+pub fn ordenar(frames: &mut Vec<Frame>, orden: Orden) {
+    match orden {
+        Orden::Lexico => frames.sort_by(|a, b| a.path.cmp(&b.path)),
+        Orden::Natural => frames.sort_by(|a, b| comparar_rutas(&a.path, &b.path)),
+        Orden::Mtime => ordenar_por_clave(frames, |f| fecha_modificacion(&f.path)),
+        Orden::Exif => ordenar_por_clave(frames, |f| f.capturado),
+    }
+}
+
+// Ordena por una clave calculada una sola vez por archivo. Los archivos
+// sin clave quedan al final y los empates se resuelven por orden natural.
+fn ordenar_por_clave<K: Ord>(frames: &mut Vec<Frame>, clave: impl Fn(&Frame) -> Option<K>) {
+    let mut con_clave: Vec<_> = frames.drain(..).map(|f| (clave(&f), f)).collect();
+    con_clave.sort_by(|(ka, a), (kb, b)| {
+        let orden = match (ka, kb) {
+            (Some(x), Some(y)) => x.cmp(y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        orden.then_with(|| comparar_rutas(&a.path, &b.path))
+    });
+    frames.extend(con_clave.into_iter().map(|(_, f)| f));
+}
+
+fn comparar_rutas(a: &Path, b: &Path) -> Ordering {
+    comparar_natural(&a.to_string_lossy(), &b.to_string_lossy())
+}
+
+// Compara dos cadenas tratando cada secuencia de dígitos como un número
+pub fn comparar_natural(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let na = tomar_numero(&mut a);
+                let nb = tomar_numero(&mut b);
+                let orden = comparar_numeros(&na, &nb);
+                if orden != Ordering::Equal {
+                    return orden;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn tomar_numero(chars: &mut Peekable<Chars>) -> String {
+    let mut numero = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        numero.push(c);
+    }
+    numero
+}
+
+// Compara por valor sin convertir a entero, así no hay desbordes con
+// números largos. Con el mismo valor va primero el que tiene menos ceros.
+fn comparar_numeros(a: &str, b: &str) -> Ordering {
+    let sa = a.trim_start_matches('0');
+    let sb = b.trim_start_matches('0');
+    sa.len()
+        .cmp(&sb.len())
+        .then_with(|| sa.cmp(sb))
+        .then_with(|| a.len().cmp(&b.len()))
+}
+
+fn fecha_modificacion(path: &Path) -> Option<std::time::SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ordenados(nombres: &[&str]) -> Vec<String> {
+        let mut nombres: Vec<String> = nombres.iter().map(|n| n.to_string()).collect();
+        nombres.sort_by(|a, b| comparar_natural(a, b));
+        nombres
+    }
+
+    #[test]
+    fn los_numeros_se_comparan_por_valor() {
+        assert_eq!(comparar_natural("IMG_2", "IMG_10"), Ordering::Less);
+        assert_eq!(comparar_natural("IMG_10", "IMG_2"), Ordering::Greater);
+        assert_eq!(ordenados(&["IMG_10.jpg", "IMG_9.jpg", "IMG_100.jpg", "IMG_1.jpg"]), [
+            "IMG_1.jpg",
+            "IMG_9.jpg",
+            "IMG_10.jpg",
+            "IMG_100.jpg"
+        ]);
+    }
+
+    #[test]
+    fn los_ceros_a_la_izquierda_solo_desempatan() {
+        assert_eq!(comparar_natural("IMG_007", "IMG_007"), Ordering::Equal);
+        assert_eq!(comparar_natural("IMG_007", "IMG_8"), Ordering::Less);
+        assert_eq!(comparar_natural("IMG_010", "IMG_9"), Ordering::Greater);
+        // Con el mismo valor va primero el que tiene menos ceros
+        assert_eq!(comparar_natural("1", "01"), Ordering::Less);
+        assert_eq!(comparar_natural("01", "1"), Ordering::Greater);
+        assert_eq!(comparar_natural("0", "00"), Ordering::Less);
+    }
+
+    #[test]
+    fn los_numeros_largos_no_desbordan() {
+        // Más dígitos de los que entran en un u64
+        let grande = "123456789012345678901234567890";
+        let mayor = "123456789012345678901234567891";
+        assert_eq!(comparar_natural(grande, mayor), Ordering::Less);
+        assert_eq!(comparar_natural(&format!("a{}", grande), "a99999999999999999999"), Ordering::Greater);
+        assert_eq!(comparar_natural(&format!("000{}", grande), grande), Ordering::Greater);
+        assert_eq!(comparar_natural(grande, grande), Ordering::Equal);
+    }
+
+    #[test]
+    fn mezcla_texto_y_numeros() {
+        assert_eq!(comparar_natural("sesion2/IMG_10", "sesion10/IMG_2"), Ordering::Less);
+        assert_eq!(comparar_natural("a10b2", "a10b10"), Ordering::Less);
+        assert_eq!(comparar_natural("a10b", "a10c"), Ordering::Less);
+        assert_eq!(comparar_natural("a10", "a10b"), Ordering::Less);
+        // Un dígito va antes que una letra, como en el orden léxico
+        assert_eq!(comparar_natural("a1", "ab"), Ordering::Less);
+        assert_eq!(ordenados(&["x2y10", "x2y9", "x10y1", "x2"]), ["x2", "x2y9", "x2y10", "x10y1"]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use image::RgbImage;
+
+use crate::error;
+use crate::filtros::FrameFilter;
+use crate::frames::Frame;
+
+// Imagen decodificada por un hilo de precarga
+pub struct Preparado {
+    pub imagen: RgbImage,
+    // Las tarjetas de separación ya tienen el tamaño final y no se filtran
+    pub tarjeta: bool,
+}
+
+// Lo que comparten los hilos que decodifican con el que codifica
+struct Estado {
+    // Frames terminados que el consumidor todavía no pidió
+    listos: BTreeMap<usize, error::Result<Preparado>>,
+    // Bytes de las imágenes en `listos` y de las reservadas por los hilos
+    // para los frames que están preparando
+    en_uso: usize,
+    // Índice que espera el consumidor
+    siguiente: usize,
+    cancelado: bool,
+}
+
+struct Compartido {
+    estado: Mutex<Estado>,
+    cambio: Condvar,
+    // Próximo frame sin asignar a un hilo
+    proximo: AtomicUsize,
+    limite_memoria: usize,
+    // Bytes que se reservan por cada frame antes de empezar a prepararlo
+    reserva: usize,
+}
+
+impl Compartido {
+    fn estado(&self) -> MutexGuard<'_, Estado> {
+        self.estado.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Frames preparados en el orden original
+pub struct Cola<'a> {
+    compartido: &'a Compartido,
+    total: usize,
+}
+
+impl Cola<'_> {
+    // Espera a que el próximo frame esté listo. Al tomarlo se libera su
+    // memoria para que los hilos sigan adelantándose. Devuelve None al
+    // terminar o si un hilo de precarga entró en pánico.
+    pub fn siguiente(&mut self) -> Option<(usize, error::Result<Preparado>)> {
+        let mut estado = self.compartido.estado();
+        let indice = estado.siguiente;
+        if indice >= self.total {
+            return None;
+        }
+        loop {
+            if let Some(resultado) = estado.listos.remove(&indice) {
+                if let Ok(preparado) = &resultado {
+                    estado.en_uso -= preparado.imagen.as_raw().len();
+                }
+                estado.siguiente += 1;
+                self.compartido.cambio.notify_all();
+                return Some((indice, resultado));
+            }
+            if estado.cancelado {
+                return None;
+            }
+            estado = self.compartido.cambio.wait(estado).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+// Marca la cola como cancelada si el hilo de precarga termina por un pánico,
+// así el consumidor no espera para siempre un frame que no va a llegar
+struct Guardia<'a>(&'a Compartido);
+
+impl Drop for Guardia<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.estado().cancelado = true;
+            self.0.cambio.notify_all();
+        }
+    }
+}
+
+// Decodifica los frames en `filtros.len()` hilos mientras `consumir` los usa
+// en orden. Cada hilo tiene su propia copia de los filtros que se corren en
+// paralelo. Antes de preparar un frame cada hilo reserva `reserva` bytes y se
+// frena si las imágenes sin consumir y las reservas pasan `limite_memoria`,
+// salvo con la que espera el consumidor, así nunca se traban. Si un hilo
+// entra en pánico la cola se corta y el pánico sigue en el hilo que llamó,
+// como si hubiera ocurrido en un filtro del consumidor.
+pub fn precargar<R>(
+    frames: &[Frame],
+    filtros: Vec<Vec<Box<dyn FrameFilter>>>,
+    limite_memoria: usize,
+    reserva: usize,
+    preparar: impl Fn(usize, &Frame, &mut [Box<dyn FrameFilter>]) -> error::Result<Preparado> + Sync,
+    consumir: impl FnOnce(&mut Cola<'_>) -> R,
+) -> R {
+    let compartido = Compartido {
+        estado: Mutex::new(Estado {
+            listos: BTreeMap::new(),
+            en_uso: 0,
+            siguiente: 0,
+            cancelado: false,
+        }),
+        cambio: Condvar::new(),
+        proximo: AtomicUsize::new(0),
+        limite_memoria,
+        reserva,
+    };
+
+    thread::scope(|s| {
+        for mut filtros in filtros {
+            let compartido = &compartido;
+            let preparar = &preparar;
+            s.spawn(move || {
+                let _guardia = Guardia(compartido);
+                loop {
+                    let indice = compartido.proximo.fetch_add(1, Ordering::Relaxed);
+                    if indice >= frames.len() {
+                        break;
+                    }
+                    let mut estado = compartido.estado();
+                    while !estado.cancelado
+                        && indice != estado.siguiente
+                        && estado.en_uso + compartido.reserva > compartido.limite_memoria
+                    {
+                        estado = compartido.cambio.wait(estado).unwrap_or_else(|e| e.into_inner());
+                    }
+                    if estado.cancelado {
+                        break;
+                    }
+                    estado.en_uso += compartido.reserva;
+                    drop(estado);
+
+                    let resultado = preparar(indice, &frames[indice], &mut filtros);
+                    let bytes = resultado.as_ref().map_or(0, |p| p.imagen.as_raw().len());
+
+                    // La reserva se cambia por lo que ocupa la imagen terminada
+                    let mut estado = compartido.estado();
+                    estado.en_uso = estado.en_uso - compartido.reserva + bytes;
+                    if estado.cancelado {
+                        break;
+                    }
+                    estado.listos.insert(indice, resultado);
+                    compartido.cambio.notify_all();
+                }
+            });
+        }
+
+        let resultado = consumir(&mut Cola {
+            compartido: &compartido,
+            total: frames.len(),
+        });
+        // Si el consumidor cortó antes, los hilos que esperan lugar se liberan
+        compartido.estado().cancelado = true;
+        compartido.cambio.notify_all();
+        resultado
+    })
+}
@@ -0,0 +1,51 @@
+use std::path::Path;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+// Patrones --incluir/--excluir que se aplican al nombre de cada archivo
+pub struct Seleccion {
+    incluir: Option<GlobSet>,
+    excluir: GlobSet,
+}
+
+impl Seleccion {
+    pub fn new(incluir: &[String], excluir: &[String]) -> Result<Self, globset::Error> {
+        let incluir = if incluir.is_empty() {
+            None
+        } else {
+            Some(construir(incluir)?)
+        };
+        Ok(Seleccion {
+            incluir,
+            excluir: construir(excluir)?,
+        })
+    }
+
+    // Motivo por el que el archivo queda fuera, o None si se acepta
+    pub fn rechazo(&self, path: &Path) -> Option<&'static str> {
+        let nombre = path.file_name()?;
+        if self.incluir.as_ref().is_some_and(|g| !g.is_match(nombre)) {
+            return Some("no coincide con --incluir");
+        }
+        if self.excluir.is_match(nombre) {
+            return Some("coincide con --excluir");
+        }
+        None
+    }
+}
+
+impl Default for Seleccion {
+    fn default() -> Self {
+        Seleccion {
+            incluir: None,
+            excluir: GlobSet::empty(),
+        }
+    }
+}
+
+fn construir(patrones: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut set = GlobSetBuilder::new();
+    for patron in patrones {
+        set.add(GlobBuilder::new(patron).case_insensitive(true).build()?);
+    }
+    set.build()
+}
@@ -0,0 +1,152 @@
+use image::{Rgb, RgbImage};
+
+// Fuente de mapa de bits de 5x7. Cada fila es un byte y el bit 4 es la
+// columna de la izquierda.
+const ANCHO_GLIFO: u32 = 5;
+const ALTO_GLIFO: u32 = 7;
+
+fn glifo(c: char) -> [u8; 7] {
+    match c {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ' ' => [0x00; 7],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+        '\'' => [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '&' => [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+// La fuente solo tiene mayúsculas; las vocales con tilde pierden la tilde
+fn normalizar(c: char) -> char {
+    match c {
+        'á' | 'Á' | 'à' | 'À' => 'A',
+        'é' | 'É' | 'è' | 'È' => 'E',
+        'í' | 'Í' | 'ì' | 'Ì' => 'I',
+        'ó' | 'Ó' | 'ò' | 'Ò' => 'O',
+        'ú' | 'Ú' | 'ù' | 'Ù' | 'ü' | 'Ü' => 'U',
+        'ñ' | 'Ñ' => 'N',
+        _ => c.to_ascii_uppercase(),
+    }
+}
+
+// Ancho en pixeles del texto dibujado con esa escala
+pub fn ancho_texto(texto: &str, escala: u32) -> u32 {
+    let n = texto.chars().count() as u32;
+    if n == 0 {
+        return 0;
+    }
+    (n * (ANCHO_GLIFO + 1) - 1) * escala
+}
+
+pub fn alto_texto(escala: u32) -> u32 {
+    ALTO_GLIFO * escala
+}
+
+// Dibuja el texto con la esquina superior izquierda en (x, y). Lo que cae
+// fuera de la imagen se recorta.
+pub fn dibujar_texto(img: &mut RgbImage, texto: &str, x: i64, y: i64, escala: u32, color: Rgb<u8>) {
+    let escala = escala.max(1) as i64;
+    for (i, c) in texto.chars().enumerate() {
+        let origen_x = x + i as i64 * (ANCHO_GLIFO as i64 + 1) * escala;
+        for (fila, bits) in glifo(normalizar(c)).iter().enumerate() {
+            for columna in 0..ANCHO_GLIFO as i64 {
+                if bits & (0x10 >> columna) == 0 {
+                    continue;
+                }
+                let px = origen_x + columna * escala;
+                let py = y + fila as i64 * escala;
+                rellenar(img, px, py, escala, color);
+            }
+        }
+    }
+}
+
+fn rellenar(img: &mut RgbImage, x: i64, y: i64, lado: i64, color: Rgb<u8>) {
+    let (w, h) = (img.width() as i64, img.height() as i64);
+    for py in y.max(0)..(y + lado).min(h) {
+        for px in x.max(0)..(x + lado).min(w) {
+            img.put_pixel(px as u32, py as u32, color);
+        }
+    }
+}
+
+// Escala para que el texto ocupe como mucho el 90% del ancho
+pub fn escala_para(texto: &str, width: u32, height: u32) -> u32 {
+    let mut escala = (height / 120).max(1);
+    while escala > 1 && ancho_texto(texto, escala) > width * 9 / 10 {
+        escala -= 1;
+    }
+    escala
+}
+
+// Cuadro negro con el título centrado en blanco
+pub fn tarjeta(width: u32, height: u32, titulo: &str) -> RgbImage {
+    let mut img = RgbImage::new(width, height);
+    let escala = escala_para(titulo, width, height);
+    let x = (width as i64 - ancho_texto(titulo, escala) as i64) / 2;
+    let y = (height as i64 - alto_texto(escala) as i64) / 2;
+    dibujar_texto(&mut img, titulo, x, y, escala, Rgb([255, 255, 255]));
+    img
+}
+
+// Dibuja la leyenda abajo a la izquierda sobre una franja oscurecida
+pub fn leyenda(img: &mut RgbImage, texto: &str) {
+    let escala = (img.height() / 240).max(1);
+    let margen = alto_texto(escala) / 2;
+    let alto_franja = (alto_texto(escala) + margen * 2).min(img.height());
+    let y0 = img.height() - alto_franja;
+    for y in y0..img.height() {
+        for x in 0..img.width() {
+            let p = img.get_pixel_mut(x, y);
+            p.0 = p.0.map(|c| c / 3);
+        }
+    }
+    dibujar_texto(img, texto, margen as i64, (y0 + margen) as i64, escala, Rgb([255, 255, 255]));
+}
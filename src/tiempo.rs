@@ -0,0 +1,156 @@
+use std::str::FromStr;
+use ffmpeg_next::util::rational::Rational;
+
+use crate::frames::Frame;
+
+// Cuánto tiempo real equivale a cuánto tiempo de video, p. ej. "1h=2s"
+#[derive(Debug, Clone, Copy)]
+pub struct Compresion {
+    pub real_ms: i64,
+    pub video_ms: i64,
+}
+
+impl Compresion {
+    fn aplicar(&self, ms: i64) -> i64 {
+        ms * self.video_ms / self.real_ms
+    }
+}
+
+impl FromStr for Compresion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (real, video) = s
+            .split_once('=')
+            .ok_or_else(|| format!("se esperaba REAL=VIDEO, p. ej. 1h=2s: '{}'", s))?;
+        let real_ms = parse_duracion(real)?;
+        let video_ms = parse_duracion(video)?;
+        if real_ms <= 0 || video_ms <= 0 {
+            return Err(format!("las duraciones deben ser mayores a cero: '{}'", s));
+        }
+        Ok(Compresion { real_ms, video_ms })
+    }
+}
+
+// Duración con unidad (ms, s, m, h) en milisegundos. Sin unidad son segundos.
+pub fn parse_duracion(s: &str) -> Result<i64, String> {
+    let s = s.trim();
+    let corte = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (numero, unidad) = s.split_at(corte);
+    let valor: f64 = numero
+        .parse()
+        .map_err(|_| format!("duración inválida: '{}'", s))?;
+    let escala = match unidad.trim() {
+        "ms" => 1.0,
+        "" | "s" => 1_000.0,
+        "m" | "min" => 60_000.0,
+        "h" => 3_600_000.0,
+        otra => return Err(format!("unidad de tiempo desconocida: '{}'", otra)),
+    };
+    Ok((valor * escala).round() as i64)
+}
+
+// Opciones del modo de tiempo real (frame rate variable)
+#[derive(Debug, Clone, Copy)]
+pub struct OpcionesVfr {
+    pub compresion: Compresion,
+    // Duración mínima y máxima de cada frame en milisegundos
+    pub duracion_min: i64,
+    pub duracion_max: Option<i64>,
+}
+
+// Pts y duración de cada frame en la base de tiempo del encoder
+#[derive(Debug, Clone)]
+pub struct Tiempos {
+    pub time_base: Rational,
+    pub pts: Vec<i64>,
+    pub duraciones: Vec<i64>,
+}
+
+impl Tiempos {
+    // Un tick de 1/fps por imagen, salvo las que tienen duración propia
+    pub fn fijos(frames: &[Frame], fps: u32) -> Self {
+        let fps = fps.max(1) as i64;
+        let duraciones = frames
+            .iter()
+            .map(|f| f.duracion.map_or(1, |ms| ((ms * fps + 500) / 1_000).max(1)))
+            .collect();
+        Self::acumular(Rational::new(1, fps as i32), duraciones)
+    }
+
+    // Cada frame dura el tiempo hasta la siguiente captura, comprimido y
+    // limitado al rango configurado. Las imágenes sin fecha duran 1/fps.
+    // Los separadores de sesión tienen duración propia, así que la pausa
+    // entre sesiones no se estira.
+    pub fn reales(frames: &[Frame], fps: u32, opciones: &OpcionesVfr) -> Self {
+        let tick = 1_000 / fps.max(1) as i64;
+        let mut duraciones: Vec<i64> = frames
+            .windows(2)
+            .map(|par| match (par[0].capturado, par[1].capturado) {
+                (Some(t0), Some(t1)) => opciones.compresion.aplicar(t1 - t0),
+                _ => tick,
+            })
+            .collect();
+        // La última imagen no tiene siguiente captura
+        if !frames.is_empty() {
+            duraciones.push(tick);
+        }
+        for (d, frame) in duraciones.iter_mut().zip(frames) {
+            *d = (*d).max(opciones.duracion_min).max(1);
+            if let Some(max) = opciones.duracion_max {
+                *d = (*d).min(max);
+            }
+            // Una duración propia no se limita
+            if let Some(propia) = frame.duracion {
+                *d = propia.max(1);
+            }
+        }
+        Self::acumular(Rational::new(1, 1000), duraciones)
+    }
+
+    fn acumular(time_base: Rational, duraciones: Vec<i64>) -> Self {
+        let pts = duraciones
+            .iter()
+            .scan(0, |acumulado, d| {
+                let actual = *acumulado;
+                *acumulado += d;
+                Some(actual)
+            })
+            .collect();
+        Tiempos {
+            time_base,
+            pts,
+            duraciones,
+        }
+    }
+
+    // Saca el frame de la línea de tiempo: queda con duración cero y los
+    // siguientes se adelantan para no dejar un hueco
+    pub fn omitir(&mut self, indice: usize) {
+        let duracion = std::mem::take(&mut self.duraciones[indice]);
+        for pts in &mut self.pts[indice + 1..] {
+            *pts -= duracion;
+        }
+    }
+
+    // Duración del frame con ese pts. Los pts no decrecen, así que sirve
+    // para los paquetes que el encoder entrega reordenados. Los frames
+    // omitidos comparten pts con el siguiente y se saltean.
+    pub fn duracion_de(&self, pts: i64) -> Option<i64> {
+        let inicio = self.pts.partition_point(|&p| p < pts);
+        self.pts[inicio..]
+            .iter()
+            .zip(&self.duraciones[inicio..])
+            .take_while(|(p, _)| **p == pts)
+            .map(|(_, d)| *d)
+            .find(|d| *d > 0)
+    }
+
+    // Duración total del video en milisegundos
+    pub fn total_ms(&self) -> i64 {
+        let ticks: i64 = self.duraciones.iter().sum();
+        ticks * 1_000 * self.time_base.numerator() as i64 / self.time_base.denominator() as i64
+    }
+}
@@ -0,0 +1,1073 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use ffmpeg_next::{
+    codec,
+    format,
+    frame,
+    util::rational::Rational,
+    Dictionary,
+    Packet,
+};
+use image::{GenericImageView, ImageDecoder, Rgb, RgbImage};
+use rayon::prelude::*;
+
+use crate::codec::{bitrate_para_tamano, dos_pasadas, Calidad, Codec, Estadisticas};
+use crate::conversion::{Color, Conversor, Convertidor, Matriz, Rango};
+use crate::error::{self, Error, Etapa};
+use crate::fallos::Politica;
+use crate::filtros::{Ajustar, AjusteDimensiones, Contexto, Encuadrar, FrameFilter, ModoAjuste, Resolucion};
+use crate::frames::{self, Frame, Omitido, Separador};
+use crate::manifiesto;
+use crate::oclusion::{self, AccionOclusion, Ocluido};
+use crate::precarga::{self, Preparado};
+use crate::orden::Orden;
+use crate::seleccion::Seleccion;
+use crate::texto;
+use crate::tiempo::{OpcionesVfr, Tiempos};
+
+// Avisos que recibe la función de progreso mientras se arma el video
+pub enum Progreso<'a> {
+    // Imágenes encontradas, ya ordenadas, y archivos descartados
+    Cargado {
+        frames: &'a [Frame],
+        omitidos: &'a [Omitido],
+    },
+    // Se empieza a procesar el frame `indice` de `total`
+    Frame {
+        indice: usize,
+        total: usize,
+        frame: &'a Frame,
+    },
+    // El frame falló y se siguió según la política de errores
+    Fallo { indice: usize, error: &'a Error },
+    // Empieza una pasada de la codificación en dos pasadas. En la primera
+    // no se avisa de cada frame ni de los fallos: se repiten en la segunda.
+    Pasada { numero: u32, total: u32 },
+    // Hay más de una sesión y se separaron en la línea de tiempo
+    Sesiones { cantidad: usize },
+    // Frames tapados que encontró el análisis de oclusión
+    Oclusion {
+        ocluidos: &'a [Ocluido],
+        accion: AccionOclusion,
+    },
+    // La resolución no servía para el formato de pixel y se cambió
+    Ajuste {
+        original: (u32, u32),
+        ajustada: (u32, u32),
+        modo: AjusteDimensiones,
+    },
+}
+
+type FuncionProgreso = Box<dyn FnMut(Progreso<'_>)>;
+
+// De dónde salen las imágenes
+enum Fuente {
+    Carpetas(Vec<PathBuf>),
+    Lista(PathBuf),
+}
+
+// Configura un timelapse. Las opciones sin valor usan los mismos valores
+// por defecto que la línea de comandos.
+pub struct TimelapseBuilder {
+    carpetas: Vec<PathBuf>,
+    lista: Option<PathBuf>,
+    recursivo: bool,
+    incluir: Vec<String>,
+    excluir: Vec<String>,
+    orden: Orden,
+    separador: Option<(Separador, i64)>,
+    fps: u32,
+    tiempo_real: Option<OpcionesVfr>,
+    oclusion: Option<(AccionOclusion, f64)>,
+    salida: PathBuf,
+    codec: Codec,
+    calidad: Calidad,
+    matriz: Option<Matriz>,
+    rango: Option<Rango>,
+    conversor: Conversor,
+    pixel: Option<format::Pixel>,
+    resolucion: Option<Resolucion>,
+    orientacion_exif: bool,
+    modo_ajuste: ModoAjuste,
+    color_relleno: Rgb<u8>,
+    ajuste_dimensiones: AjusteDimensiones,
+    multiplo_16: bool,
+    tamano_objetivo: Option<u64>,
+    hilos: usize,
+    memoria_precarga: usize,
+    politica: Politica,
+    opciones_codec: Vec<(String, String)>,
+    filtros: Vec<Box<dyn FrameFilter>>,
+    progreso: Option<FuncionProgreso>,
+}
+
+impl Default for TimelapseBuilder {
+    fn default() -> Self {
+        TimelapseBuilder {
+            carpetas: Vec::new(),
+            lista: None,
+            recursivo: false,
+            incluir: Vec::new(),
+            excluir: Vec::new(),
+            orden: Orden::Natural,
+            separador: None,
+            fps: 10,
+            tiempo_real: None,
+            oclusion: None,
+            salida: PathBuf::from("timelapse.mp4"),
+            codec: Codec::H264,
+            calidad: Calidad::default(),
+            matriz: None,
+            rango: None,
+            conversor: Conversor::Swscale,
+            pixel: None,
+            resolucion: None,
+            orientacion_exif: true,
+            modo_ajuste: ModoAjuste::Encajar,
+            color_relleno: Rgb([0, 0, 0]),
+            ajuste_dimensiones: AjusteDimensiones::Recortar,
+            multiplo_16: false,
+            tamano_objetivo: None,
+            hilos: thread::available_parallelism().map_or(1, |n| n.get()),
+            memoria_precarga: 1_000_000_000,
+            politica: Politica::Abort,
+            opciones_codec: Vec::new(),
+            filtros: Vec::new(),
+            progreso: None,
+        }
+    }
+}
+
+impl TimelapseBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Agrega una carpeta de imágenes; cada carpeta es una sesión
+    pub fn carpeta(mut self, carpeta: impl Into<PathBuf>) -> Self {
+        self.carpetas.push(carpeta.into());
+        self
+    }
+
+    pub fn carpetas<I, P>(mut self, carpetas: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.carpetas.extend(carpetas.into_iter().map(Into::into));
+        self
+    }
+
+    // Usa un manifiesto en lugar de carpetas
+    pub fn lista(mut self, lista: impl Into<PathBuf>) -> Self {
+        self.lista = Some(lista.into());
+        self
+    }
+
+    pub fn recursivo(mut self, recursivo: bool) -> Self {
+        self.recursivo = recursivo;
+        self
+    }
+
+    pub fn incluir(mut self, patron: impl Into<String>) -> Self {
+        self.incluir.push(patron.into());
+        self
+    }
+
+    pub fn excluir(mut self, patron: impl Into<String>) -> Self {
+        self.excluir.push(patron.into());
+        self
+    }
+
+    pub fn orden(mut self, orden: Orden) -> Self {
+        self.orden = orden;
+        self
+    }
+
+    // Separador entre sesiones con su duración en milisegundos
+    pub fn separador(mut self, separador: Separador, duracion_ms: i64) -> Self {
+        self.separador = Some((separador, duracion_ms));
+        self
+    }
+
+    pub fn fps(mut self, fps: u32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    // Frame rate variable según las fechas de captura
+    pub fn tiempo_real(mut self, opciones: OpcionesVfr) -> Self {
+        self.tiempo_real = Some(opciones);
+        self
+    }
+
+    // Analiza los frames antes de codificar y descarta o reemplaza los que
+    // tienen tapada más de la fracción `umbral` de la imagen
+    pub fn oclusion(mut self, accion: AccionOclusion, umbral: f64) -> Self {
+        self.oclusion = Some((accion, umbral));
+        self
+    }
+
+    pub fn salida(mut self, salida: impl Into<PathBuf>) -> Self {
+        self.salida = salida.into();
+        self
+    }
+
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    // CRF, tasa de bits, preset, etc.; se traducen a las opciones del encoder
+    pub fn calidad(mut self, calidad: Calidad) -> Self {
+        self.calidad = calidad;
+        self
+    }
+
+    // Matriz RGB a YCbCr; sin indicarla se elige según la altura del video
+    pub fn matriz(mut self, matriz: Matriz) -> Self {
+        self.matriz = Some(matriz);
+        self
+    }
+
+    pub fn rango(mut self, rango: Rango) -> Self {
+        self.rango = Some(rango);
+        self
+    }
+
+    pub fn conversor(mut self, conversor: Conversor) -> Self {
+        self.conversor = conversor;
+        self
+    }
+
+    // Formato de pixel del encoder en lugar del que corresponde al códec
+    pub fn formato_pixel(mut self, pixel: format::Pixel) -> Self {
+        self.pixel = Some(pixel);
+        self
+    }
+
+    // Resolución a la que se lleva cada imagen antes de los filtros; sin
+    // indicarla es la de la primera imagen. Es la del video salvo que un
+    // filtro cambie el tamaño, p. ej. rotar=90.
+    pub fn resolucion(mut self, resolucion: Resolucion) -> Self {
+        self.resolucion = Some(resolucion);
+        self
+    }
+
+    // Gira las fotos según la orientación EXIF al leerlas (activado por
+    // defecto)
+    pub fn orientacion_exif(mut self, orientacion_exif: bool) -> Self {
+        self.orientacion_exif = orientacion_exif;
+        self
+    }
+
+    // Cómo entran en la resolución del video las imágenes de otra proporción
+    pub fn modo_ajuste(mut self, modo: ModoAjuste) -> Self {
+        self.modo_ajuste = modo;
+        self
+    }
+
+    // Color de las bandas con ModoAjuste::Encajar
+    pub fn color_relleno(mut self, color: Rgb<u8>) -> Self {
+        self.color_relleno = color;
+        self
+    }
+
+    // Qué hacer si la resolución no es par en los formatos con croma
+    // submuestreada
+    pub fn ajuste_dimensiones(mut self, modo: AjusteDimensiones) -> Self {
+        self.ajuste_dimensiones = modo;
+        self
+    }
+
+    // Lleva el ancho y el alto a múltiplos de 16
+    pub fn multiplo_16(mut self, multiplo_16: bool) -> Self {
+        self.multiplo_16 = multiplo_16;
+        self
+    }
+
+    // Codifica en dos pasadas para que el video pese cerca de `bytes`
+    pub fn tamano_objetivo(mut self, bytes: u64) -> Self {
+        self.tamano_objetivo = Some(bytes);
+        self
+    }
+
+    // Hilos que leen y filtran imágenes por adelantado
+    pub fn hilos(mut self, hilos: usize) -> Self {
+        self.hilos = hilos;
+        self
+    }
+
+    // Bytes que pueden ocupar las imágenes leídas por adelantado que el
+    // encoder todavía no tomó
+    pub fn memoria_precarga(mut self, bytes: usize) -> Self {
+        self.memoria_precarga = bytes;
+        self
+    }
+
+    pub fn politica(mut self, politica: Politica) -> Self {
+        self.politica = politica;
+        self
+    }
+
+    // Opción privada del encoder, p. ej. ("x264-params", "keyint=60"); se
+    // aplica después de las de calidad y puede reemplazarlas
+    pub fn opcion_codec(mut self, clave: impl Into<String>, valor: impl Into<String>) -> Self {
+        self.opciones_codec.push((clave.into(), valor.into()));
+        self
+    }
+
+    // Los filtros se aplican en el orden en que se agregan, después de
+    // llevar cada imagen a la resolución según el modo de ajuste
+    pub fn filtro(mut self, filtro: impl FrameFilter + 'static) -> Self {
+        self.filtros.push(Box::new(filtro));
+        self
+    }
+
+    // Agrega filtros ya creados, p. ej. con filtros::crear o filtros::leer_archivo
+    pub fn filtros(mut self, filtros: impl IntoIterator<Item = Box<dyn FrameFilter>>) -> Self {
+        self.filtros.extend(filtros);
+        self
+    }
+
+    pub fn progreso(mut self, progreso: impl FnMut(Progreso<'_>) + 'static) -> Self {
+        self.progreso = Some(Box::new(progreso));
+        self
+    }
+
+    pub fn build(self) -> error::Result<Timelapse> {
+        let fuente = match (self.lista, self.carpetas.is_empty()) {
+            (Some(_), false) => {
+                return Err(Error::Configuracion(
+                    "se indicó una lista y también carpetas".to_string(),
+                ));
+            }
+            (Some(lista), true) => Fuente::Lista(lista),
+            (None, false) => Fuente::Carpetas(self.carpetas),
+            (None, true) => {
+                return Err(Error::Configuracion("no se indicó ninguna carpeta".to_string()));
+            }
+        };
+        if self.fps == 0 {
+            return Err(Error::Configuracion("los fps deben ser mayores a cero".to_string()));
+        }
+        if let Some(OpcionesVfr { duracion_min, duracion_max: Some(duracion_max), .. }) = self.tiempo_real
+            && duracion_min > duracion_max
+        {
+            return Err(Error::Configuracion(format!(
+                "la duración mínima de un frame ({} ms) supera a la máxima ({} ms)",
+                duracion_min, duracion_max
+            )));
+        }
+        if self.oclusion.is_some_and(|(_, umbral)| !(umbral > 0.0 && umbral < 1.0)) {
+            return Err(Error::Configuracion(
+                "el umbral de oclusión tiene que estar entre 0 y 100%".to_string(),
+            ));
+        }
+        if self.hilos == 0 {
+            return Err(Error::Configuracion("se necesita al menos un hilo".to_string()));
+        }
+        self.codec.validar_contenedor(&self.salida)?;
+        if self.codec == Codec::Mjpeg && self.rango == Some(Rango::Limitado) && self.pixel.is_none() {
+            return Err(Error::Configuracion("MJPEG requiere rango completo".to_string()));
+        }
+        if self.tamano_objetivo.is_some() && (self.calidad.crf.is_some() || self.calidad.bitrate.is_some()) {
+            return Err(Error::Configuracion(
+                "el tamaño objetivo no se puede combinar con --crf ni --bitrate".to_string(),
+            ));
+        }
+        // El encoder se elige acá, así un códec sin encoder o sin dos
+        // pasadas falla antes de leer las imágenes
+        let pixel = self.pixel.unwrap_or(self.codec.pixel());
+        let encoder = self.codec.encoder(pixel, self.tamano_objetivo.is_some())?;
+        self.conversor.validar(pixel)?;
+        Ok(Timelapse {
+            fuente,
+            recursivo: self.recursivo,
+            seleccion: Seleccion::new(&self.incluir, &self.excluir)?,
+            orden: self.orden,
+            separador: self.separador,
+            fps: self.fps,
+            tiempo_real: self.tiempo_real,
+            oclusion: self.oclusion,
+            salida: self.salida,
+            calidad: self.calidad,
+            matriz: self.matriz,
+            rango: self.rango,
+            conversor: self.conversor,
+            pixel,
+            encoder,
+            resolucion: self.resolucion,
+            orientacion_exif: self.orientacion_exif,
+            modo_ajuste: self.modo_ajuste,
+            color_relleno: self.color_relleno,
+            ajuste_dimensiones: self.ajuste_dimensiones,
+            multiplo_16: self.multiplo_16,
+            tamano_objetivo: self.tamano_objetivo,
+            hilos: self.hilos,
+            memoria_precarga: self.memoria_precarga,
+            politica: self.politica,
+            opciones_codec: self.opciones_codec,
+            filtros: self.filtros,
+            progreso: self.progreso.unwrap_or_else(|| Box::new(|_| {})),
+        })
+    }
+}
+
+// Resultado de un timelapse terminado
+#[derive(Debug)]
+pub struct Resumen {
+    // Frames enviados al encoder, incluidos los repetidos
+    pub codificados: usize,
+    // Frames que fallaron y se omitieron o repitieron
+    pub fallos: Vec<Error>,
+    pub duracion_ms: i64,
+}
+
+pub struct Timelapse {
+    fuente: Fuente,
+    recursivo: bool,
+    seleccion: Seleccion,
+    orden: Orden,
+    separador: Option<(Separador, i64)>,
+    fps: u32,
+    tiempo_real: Option<OpcionesVfr>,
+    oclusion: Option<(AccionOclusion, f64)>,
+    salida: PathBuf,
+    calidad: Calidad,
+    matriz: Option<Matriz>,
+    rango: Option<Rango>,
+    conversor: Conversor,
+    pixel: format::Pixel,
+    encoder: ffmpeg_next::Codec,
+    resolucion: Option<Resolucion>,
+    orientacion_exif: bool,
+    modo_ajuste: ModoAjuste,
+    color_relleno: Rgb<u8>,
+    ajuste_dimensiones: AjusteDimensiones,
+    multiplo_16: bool,
+    tamano_objetivo: Option<u64>,
+    hilos: usize,
+    memoria_precarga: usize,
+    politica: Politica,
+    opciones_codec: Vec<(String, String)>,
+    filtros: Vec<Box<dyn FrameFilter>>,
+    progreso: FuncionProgreso,
+}
+
+impl Timelapse {
+    pub fn builder() -> TimelapseBuilder {
+        TimelapseBuilder::new()
+    }
+
+    pub fn ejecutar(mut self) -> error::Result<Resumen> {
+        ffmpeg_next::init().map_err(Error::ffmpeg(Etapa::Codificacion))?;
+
+        // Leer imágenes con sus metadatos
+        let (mut frames, omitidos) = match &self.fuente {
+            Fuente::Lista(lista) => manifiesto::cargar(lista)?,
+            Fuente::Carpetas(carpetas) => {
+                frames::cargar(carpetas, self.recursivo, &self.seleccion, self.orden)?
+            }
+        };
+        (self.progreso)(Progreso::Cargado {
+            frames: &frames,
+            omitidos: &omitidos,
+        });
+
+        if frames.is_empty() {
+            return Err(Error::SinImagenes);
+        }
+        if let Some((separador, duracion)) = self.separador {
+            let cantidad;
+            (frames, cantidad) = frames::separar_sesiones(frames, separador, duracion);
+            if cantidad > 1 {
+                (self.progreso)(Progreso::Sesiones { cantidad });
+            }
+        }
+        if let Some((accion, umbral)) = self.oclusion {
+            let ocluidos = self.analizar_oclusion(&frames, umbral)?;
+            (self.progreso)(Progreso::Oclusion {
+                ocluidos: &ocluidos,
+                accion,
+            });
+            frames = oclusion::aplicar(frames, &ocluidos, accion);
+        }
+
+        // Con tiempo real el pts sale de las fechas de captura, en milisegundos
+        let tiempos = match &self.tiempo_real {
+            Some(opciones) => Tiempos::reales(&frames, self.fps, opciones),
+            None => Tiempos::fijos(&frames, self.fps),
+        };
+
+        // El primer filtro lleva cada imagen a la resolución pedida o a la
+        // de la primera imagen que se pueda leer. Así todo el resto de la
+        // cadena recibe frames del mismo tamaño y la redimensión corre en
+        // los hilos de precarga. El tamaño del video es el que sale de la
+        // cadena.
+        self.filtros.insert(0, Box::new(Encuadrar::new(self.resolucion, self.modo_ajuste, self.color_relleno)));
+        let referencia = dimensiones_referencia(&frames, self.politica, self.orientacion_exif)?;
+        let (mut width, mut height) = self
+            .filtros
+            .iter_mut()
+            .fold(referencia, |dimensiones, filtro| filtro.configurar(dimensiones));
+
+        let (pixel, encoder_ffmpeg) = (self.pixel, self.encoder);
+
+        // Con croma submuestreada el ancho o el alto tienen que ser pares
+        let multiplo = match (self.multiplo_16, pixel.descriptor()) {
+            (true, _) => (16, 16),
+            (false, Some(d)) => (1 << d.log2_chroma_w(), 1 << d.log2_chroma_h()),
+            (false, None) => (1, 1),
+        };
+        if width % multiplo.0 != 0 || height % multiplo.1 != 0 {
+            let mut ajuste = Ajustar::new(self.ajuste_dimensiones, multiplo);
+            let ajustada = ajuste.configurar((width, height));
+            (self.progreso)(Progreso::Ajuste {
+                original: (width, height),
+                ajustada,
+                modo: self.ajuste_dimensiones,
+            });
+            self.filtros.push(Box::new(ajuste));
+            (width, height) = ajustada;
+        }
+        // Los formatos "J" (los de MJPEG) son de rango completo
+        let rango = self.rango.unwrap_or(match pixel {
+            format::Pixel::YUVJ420P | format::Pixel::YUVJ422P | format::Pixel::YUVJ444P => Rango::Completo,
+            _ => Rango::Limitado,
+        });
+        let formato = Formato {
+            width,
+            height,
+            referencia,
+            pixel,
+            color: Color::para(height, self.matriz, rango),
+        };
+        let Some(bytes) = self.tamano_objetivo else {
+            return self.codificar(&frames, tiempos, formato, encoder_ffmpeg, None);
+        };
+
+        // Dos pasadas: la primera solo junta estadísticas y la segunda
+        // reparte la tasa calculada para llegar al tamaño pedido
+        let bitrate = bitrate_para_tamano(bytes, tiempos.total_ms())?;
+        let carpeta = carpeta_temporal()?;
+        let archivo = carpeta.join("pasada.log");
+        let mut codificar_pasada = |numero| {
+            (self.progreso)(Progreso::Pasada { numero, total: 2 });
+            let pasada = Pasada {
+                numero,
+                bitrate,
+                estadisticas: dos_pasadas(encoder_ffmpeg.name(), numero, &archivo)?,
+                archivo: &archivo,
+            };
+            self.codificar(&frames, tiempos.clone(), formato, encoder_ffmpeg, Some(&pasada))
+        };
+        let resultado = codificar_pasada(1).and_then(|_| codificar_pasada(2));
+        let _ = fs::remove_dir_all(&carpeta);
+        resultado
+    }
+
+    // Lee todos los frames en miniatura, en paralelo, y busca los tapados. Los
+    // que no se pueden leer quedan para la política de errores al codificar.
+    fn analizar_oclusion(&self, frames: &[Frame], umbral: f64) -> error::Result<Vec<Ocluido>> {
+        let hilos = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.hilos)
+            .build()
+            .map_err(|e| Error::Configuracion(format!("no se pudieron crear los hilos: {}", e)))?;
+        let orientar = self.orientacion_exif;
+        let miniaturas: Vec<_> = hilos.install(|| {
+            frames
+                .par_iter()
+                .enumerate()
+                .map(|(i, entrada)| {
+                    if entrada.titulo.is_some() {
+                        return None;
+                    }
+                    let mut img = abrir_imagen(i, &entrada.path, orientar).ok()?;
+                    if let Some(recorte) = &entrada.recorte {
+                        img = recorte.aplicar(&img);
+                    }
+                    Some(oclusion::miniatura(&img))
+                })
+                .collect()
+        });
+        Ok(oclusion::detectar(frames, &miniaturas, umbral))
+    }
+
+    // Codifica todos los frames. Sin `pasada` es una codificación normal; en
+    // la primera de dos pasadas no se escribe la salida, solo las estadísticas.
+    fn codificar(
+        &mut self,
+        frames: &[Frame],
+        mut tiempos: Tiempos,
+        Formato { width, height, referencia, pixel, color }: Formato,
+        encoder_ffmpeg: ffmpeg_next::Codec,
+        pasada: Option<&Pasada<'_>>,
+    ) -> error::Result<Resumen> {
+        let time_base = tiempos.time_base;
+        let primera = pasada.is_some_and(|p| p.numero == 1);
+
+        let mut opciones = Dictionary::new();
+        let mut flags = codec::Flags::empty();
+        let mut estadisticas_entrada = None;
+        for (clave, valor) in self.calidad.opciones(encoder_ffmpeg.name())?.iter().chain(&self.opciones_codec) {
+            opciones.set(clave, valor);
+        }
+        if let Some(pasada) = pasada {
+            opciones.set("b", &pasada.bitrate.to_string());
+            flags |= if primera { codec::Flags::PASS1 } else { codec::Flags::PASS2 };
+            match &pasada.estadisticas {
+                Estadisticas::Archivo(extra) => {
+                    for (clave, valor) in extra {
+                        opciones.set(clave, valor);
+                    }
+                }
+                Estadisticas::Contexto if !primera => {
+                    let contenido = fs::read(pasada.archivo).map_err(Error::io(pasada.archivo))?;
+                    estadisticas_entrada = Some(CString::new(contenido).map_err(|_| {
+                        Error::Configuracion("estadísticas de la primera pasada inválidas".to_string())
+                    })?);
+                }
+                Estadisticas::Contexto => {}
+            }
+        }
+
+        // Lo que puede fallar antes de empezar se prepara antes de crear el
+        // archivo, así no queda un video con cabecera y sin cierre
+        let mut convertidor = Convertidor::new(self.conversor, pixel, width, height, color)?;
+        // Los filtros con informe crean acá su CSV
+        for filtro in self.filtros.iter_mut() {
+            filtro.reiniciar()?;
+        }
+
+        // Crear contexto de salida; la primera pasada no escribe nada
+        let mut octx = match primera {
+            true => None,
+            false => Some(format::output(&self.salida).map_err(Error::ffmpeg(Etapa::Muxer))?),
+        };
+        // mp4, mov y mkv guardan la configuración del códec en la cabecera
+        if octx.as_ref().is_some_and(|o| o.format().flags().contains(format::Flags::GLOBAL_HEADER)) {
+            flags |= codec::Flags::GLOBAL_HEADER;
+        }
+        let mut encoder = codec::Context::new_with_codec(encoder_ffmpeg)
+            .encoder()
+            .video()
+            .map_err(Error::ffmpeg(Etapa::Codificacion))?;
+
+        encoder.set_width(width);
+        encoder.set_height(height);
+        encoder.set_format(pixel);
+        encoder.set_time_base(time_base);
+        encoder.set_flags(flags);
+        color.etiquetar_encoder(&mut encoder);
+        if let Some(estadisticas) = &estadisticas_entrada {
+            // ffmpeg no copia ni libera stats_in: `estadisticas_entrada` vive
+            // más que el encoder
+            unsafe {
+                (*encoder.as_mut_ptr()).stats_in = estadisticas.as_ptr() as *mut _;
+            }
+        }
+        let mut encoder = encoder
+            .open_as_with(encoder_ffmpeg, opciones)
+            .map_err(Error::ffmpeg(Etapa::Codificacion))?;
+        let mut stream_time_base = time_base;
+        if let Some(octx) = octx.as_mut() {
+            let mut stream = octx.add_stream(encoder_ffmpeg).map_err(Error::ffmpeg(Etapa::Muxer))?;
+            stream.set_time_base(time_base);
+            stream.set_parameters(&encoder);
+            octx.write_header().map_err(Error::ffmpeg(Etapa::Muxer))?;
+            // El muxer puede cambiar la base de tiempo del stream al escribir la cabecera
+            stream_time_base = octx.stream(0).unwrap().time_base();
+        }
+
+        // Procesar imágenes
+        let mut fallos = Vec::new();
+        let mut ultimo: Option<frame::Video> = None;
+        let mut codificados = 0;
+        // Cada hilo de precarga corre su copia de los filtros que se pueden
+        // duplicar; el resto de la cadena se aplica acá, en orden
+        let copias: Vec<Vec<_>> = (0..self.hilos)
+            .map(|_| self.filtros.iter().map_while(|f| f.duplicar()).collect())
+            .collect();
+        let paralelos = copias[0].len();
+        let mut cadena = Cadena::new(frames, &mut self.filtros[paralelos..]);
+        // Mientras se prepara, un frame ocupa la imagen decodificada y la que
+        // sale de los filtros
+        let (ancho_entrada, alto_entrada) = referencia;
+        let reserva = (ancho_entrada as usize * alto_entrada as usize + width as usize * height as usize) * 3;
+        let resultado = precarga::precargar(
+            frames,
+            copias,
+            self.memoria_precarga,
+            reserva,
+            |i, entrada, filtros| preparar_imagen(i, entrada, width, height, self.orientacion_exif, filtros),
+            |cola| {
+                // Los filtros que guardan frames los devuelven más tarde: lo
+                // que sale de la cadena espera acá para codificarse en orden
+                let mut listos = BTreeMap::new();
+                let mut proximo = 0;
+                let mut enviar = |listos: &mut BTreeMap<usize, error::Result<RgbImage>>, progreso: &mut FuncionProgreso| {
+                    while let Some(resultado) = listos.remove(&proximo) {
+                        let i = proximo;
+                        proximo += 1;
+                        let entrada = &frames[i];
+                        let mut f = match resultado.and_then(|img| convertir(i, entrada, &img, &mut convertidor)) {
+                            Ok(f) => f,
+                            Err(e) => {
+                                if !primera {
+                                    progreso(Progreso::Fallo { indice: i, error: &e });
+                                }
+                                match (self.politica, ultimo.take()) {
+                                    (Politica::Abort, _) => return Err(e),
+                                    (Politica::RepeatPrevious, Some(anterior)) => {
+                                        fallos.push(e);
+                                        anterior
+                                    }
+                                    // Sin frame anterior no hay nada que repetir
+                                    (Politica::Skip | Politica::RepeatPrevious, _) => {
+                                        fallos.push(e);
+                                        tiempos.omitir(i);
+                                        continue;
+                                    }
+                                }
+                            }
+                        };
+
+                        f.set_pts(Some(tiempos.pts[i]));
+                        encoder
+                            .send_frame(&f)
+                            .map_err(Error::ffmpeg_frame(i, Etapa::Codificacion))
+                            .and_then(|_| receive_and_write_packets(&mut encoder, octx.as_mut(), &tiempos, stream_time_base))?;
+                        codificados += 1;
+                        ultimo = Some(f);
+                    }
+                    Ok(())
+                };
+
+                while let Some((i, preparado)) = cola.siguiente() {
+                    // La primera de dos pasadas repite los mismos frames que
+                    // la segunda: se avisa una sola vez de cada uno
+                    if !primera {
+                        (self.progreso)(Progreso::Frame {
+                            indice: i,
+                            total: frames.len(),
+                            frame: &frames[i],
+                        });
+                    }
+                    match preparado {
+                        Ok(p) if p.tarjeta => {
+                            listos.insert(i, Ok(p.imagen));
+                        }
+                        Ok(p) => listos.extend(cadena.recibir(i, p.imagen)),
+                        Err(e) => {
+                            listos.insert(i, Err(e));
+                        }
+                    }
+                    enviar(&mut listos, &mut self.progreso)?;
+                }
+                listos.extend(cadena.terminar()?);
+                enviar(&mut listos, &mut self.progreso)
+            },
+        );
+
+        // Vaciar encoder y cerrar el archivo aunque se haya cortado el proceso,
+        // así lo codificado hasta ahí queda reproducible
+        let vaciado = encoder
+            .send_eof()
+            .map_err(Error::ffmpeg(Etapa::Codificacion))
+            .and_then(|_| receive_and_write_packets(&mut encoder, octx.as_mut(), &tiempos, stream_time_base));
+        let cierre = match octx.as_mut() {
+            Some(octx) => octx.write_trailer().map_err(Error::ffmpeg(Etapa::Muxer)),
+            None => Ok(()),
+        };
+        // Lo que se llegó a codificar acompaña al error. Se informa la
+        // primera falla en el orden del proceso, que es la que cortó.
+        if let Err(fuente) = resultado.and(vaciado).and(cierre) {
+            return Err(Error::Interrumpido {
+                fuente: Box::new(fuente),
+                codificados,
+                fallos,
+            });
+        }
+
+        // libvpx y libaom dejan las estadísticas en el contexto al terminar
+        if let Some(pasada) = pasada.filter(|_| primera)
+            && matches!(pasada.estadisticas, Estadisticas::Contexto)
+        {
+            let salida = unsafe { (*encoder.as_ptr()).stats_out };
+            if !salida.is_null() {
+                let contenido = unsafe { CStr::from_ptr(salida) };
+                fs::write(pasada.archivo, contenido.to_bytes()).map_err(Error::io(pasada.archivo))?;
+            }
+        }
+
+        Ok(Resumen {
+            codificados,
+            fallos,
+            duracion_ms: tiempos.total_ms(),
+        })
+    }
+}
+
+// Tamaño, formato de pixel y espacio de color de los frames que recibe el encoder
+#[derive(Clone, Copy)]
+struct Formato {
+    width: u32,
+    height: u32,
+    // Tamaño de la primera imagen al entrar a la cadena de filtros
+    referencia: (u32, u32),
+    pixel: format::Pixel,
+    color: Color,
+}
+
+// Una de las dos pasadas de --tamano-objetivo
+struct Pasada<'a> {
+    numero: u32,
+    bitrate: u64,
+    estadisticas: Estadisticas,
+    archivo: &'a Path,
+}
+
+// Carpeta para las estadísticas de dos pasadas, distinta en cada corrida
+// aunque haya varias en el mismo proceso o quede una de un proceso anterior
+// con el mismo pid
+fn carpeta_temporal() -> error::Result<PathBuf> {
+    static CORRIDAS: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let numero = CORRIDAS.fetch_add(1, Ordering::Relaxed);
+        let carpeta = env::temp_dir().join(format!("timelapse_lego_{}_{}", process::id(), numero));
+        match fs::create_dir(&carpeta) {
+            Ok(()) => return Ok(carpeta),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(Error::io(&carpeta)(e)),
+        }
+    }
+}
+
+// Con --on-error=abort manda la primera imagen; con las otras políticas se
+// usa la primera que se pueda leer
+fn dimensiones_referencia(frames: &[Frame], politica: Politica, orientar: bool) -> error::Result<(u32, u32)> {
+    let mut primer_error = None;
+    for (i, entrada) in frames.iter().enumerate().filter(|(_, f)| f.titulo.is_none()) {
+        match get_image_dimensions(i, entrada, orientar) {
+            Ok(dimensiones) => return Ok(dimensiones),
+            Err(e) if politica == Politica::Abort => return Err(e),
+            Err(e) => {
+                primer_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(primer_error.unwrap_or(Error::SinImagenes))
+}
+
+// Lee la imagen del frame y la pasa por los filtros que corren en los hilos
+// de precarga. Las tarjetas de separación ya se generan al tamaño del video
+// y no se filtran.
+fn preparar_imagen(
+    indice: usize,
+    entrada: &Frame,
+    width: u32,
+    height: u32,
+    orientar: bool,
+    filtros: &mut [Box<dyn FrameFilter>],
+) -> error::Result<Preparado> {
+    let path = &entrada.path;
+    if let Some(titulo) = &entrada.titulo {
+        return Ok(Preparado {
+            imagen: texto::tarjeta(width, height, titulo),
+            tarjeta: true,
+        });
+    }
+
+    let mut img = abrir_imagen(indice, path, orientar)?;
+    if let Some(recorte) = &entrada.recorte {
+        img = recorte.aplicar(&img);
+    }
+    if img.width() == 0 || img.height() == 0 {
+        return Err(error_imagen(indice, path, Etapa::Redimension));
+    }
+
+    let ctx = Contexto { indice, frame: entrada };
+    let mut img = img.to_rgb8();
+    for filtro in filtros.iter_mut() {
+        img = filtro.aplicar(img, &ctx)?;
+    }
+    Ok(Preparado { imagen: img, tarjeta: false })
+}
+
+// Parte de la cadena de filtros que se aplica en orden en el hilo que
+// codifica. Cada frame que entra a un filtro sale una vez, en el mismo
+// orden, aunque algunos filtros lo devuelvan varios frames más tarde.
+struct Cadena<'a> {
+    frames: &'a [Frame],
+    filtros: &'a mut [Box<dyn FrameFilter>],
+    // Índices de los frames que entraron a cada filtro y todavía no salieron
+    pendientes: Vec<VecDeque<usize>>,
+}
+
+impl<'a> Cadena<'a> {
+    fn new(frames: &'a [Frame], filtros: &'a mut [Box<dyn FrameFilter>]) -> Self {
+        let pendientes = filtros.iter().map(|_| VecDeque::new()).collect();
+        Cadena { frames, filtros, pendientes }
+    }
+
+    // Pasa un frame por la cadena y devuelve, con su índice, los que
+    // terminaron de salir
+    fn recibir(&mut self, indice: usize, img: RgbImage) -> Vec<(usize, error::Result<RgbImage>)> {
+        let mut salida = Vec::new();
+        self.pasar(0, vec![(indice, img)], &mut salida);
+        salida
+    }
+
+    // Vacía los filtros en orden: lo que devuelve cada uno sigue por el resto
+    // de la cadena antes de vaciar el siguiente
+    fn terminar(&mut self) -> error::Result<Vec<(usize, error::Result<RgbImage>)>> {
+        let mut salida = Vec::new();
+        for n in 0..self.filtros.len() {
+            let imagenes = self.filtros[n].terminar()?;
+            let entradas = self.salieron(n, imagenes);
+            self.pasar(n + 1, entradas, &mut salida);
+        }
+        Ok(salida)
+    }
+
+    // Aplica los filtros desde `desde` y agrega la leyenda a lo que sale del
+    // último
+    fn pasar(
+        &mut self,
+        desde: usize,
+        mut entradas: Vec<(usize, RgbImage)>,
+        salida: &mut Vec<(usize, error::Result<RgbImage>)>,
+    ) {
+        for n in desde..self.filtros.len() {
+            let mut siguientes = Vec::new();
+            for (indice, img) in entradas {
+                let ctx = Contexto { indice, frame: &self.frames[indice] };
+                match self.filtros[n].recibir(img, &ctx) {
+                    Ok(imagenes) => {
+                        self.pendientes[n].push_back(indice);
+                        siguientes.extend(self.salieron(n, imagenes));
+                    }
+                    Err(e) => salida.push((indice, Err(e))),
+                }
+            }
+            entradas = siguientes;
+        }
+        for (indice, mut img) in entradas {
+            if let Some(leyenda) = &self.frames[indice].leyenda {
+                texto::leyenda(&mut img, leyenda);
+            }
+            salida.push((indice, Ok(img)));
+        }
+    }
+
+    // Asigna a las imágenes que devolvió el filtro `n` los índices de los
+    // frames más viejos que tenía
+    fn salieron(&mut self, n: usize, imagenes: Vec<RgbImage>) -> Vec<(usize, RgbImage)> {
+        imagenes
+            .into_iter()
+            .map_while(|img| Some((self.pendientes[n].pop_front()?, img)))
+            .collect()
+    }
+}
+
+// Pasa la imagen al frame YUV que recibe el encoder
+fn convertir(
+    indice: usize,
+    entrada: &Frame,
+    img: &RgbImage,
+    convertidor: &mut Convertidor,
+) -> error::Result<frame::Video> {
+    if !convertidor.escala() && img.dimensions() != convertidor.dimensiones() {
+        return Err(error_imagen(indice, &entrada.path, Etapa::Conversion));
+    }
+    convertidor.convertir(indice, img)
+}
+
+// Error para una imagen con dimensiones que no se pueden procesar
+fn error_imagen(indice: usize, path: &Path, etapa: Etapa) -> Error {
+    Error::Imagen {
+        indice: Some(indice),
+        path: path.to_path_buf(),
+        etapa,
+        fuente: image::ImageError::Parameter(image::error::ParameterError::from_kind(
+            image::error::ParameterErrorKind::DimensionMismatch,
+        )),
+    }
+}
+
+// Dimensiones con las que la imagen entra a la cadena de filtros
+fn get_image_dimensions(indice: usize, entrada: &Frame, orientar: bool) -> error::Result<(u32, u32)> {
+    let img = abrir_imagen(indice, &entrada.path, orientar)?;
+    Ok(match &entrada.recorte {
+        Some(recorte) => recorte.aplicar(&img).dimensions(),
+        None => img.dimensions(),
+    })
+}
+
+// El formato se detecta por el contenido, igual que al listar las carpetas.
+// Con `orientar` la imagen sale derecha según su orientación EXIF.
+fn abrir_imagen(indice: usize, path: &Path, orientar: bool) -> error::Result<image::DynamicImage> {
+    let error = |etapa| {
+        move |fuente| Error::Imagen {
+            indice: Some(indice),
+            path: path.to_path_buf(),
+            etapa,
+            fuente,
+        }
+    };
+    let mut decoder = image::ImageReader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| error(Etapa::Lectura)(image::ImageError::IoError(e)))?
+        .into_decoder()
+        .map_err(error(Etapa::Decodificacion))?;
+    // Una orientación ilegible se trata como si no estuviera
+    let orientacion = match orientar {
+        true => decoder.orientation().ok(),
+        false => None,
+    };
+    let mut img = image::DynamicImage::from_decoder(decoder).map_err(error(Etapa::Decodificacion))?;
+    if let Some(orientacion) = orientacion {
+        img.apply_orientation(orientacion);
+    }
+    Ok(img)
+}
+
+fn receive_and_write_packets(
+    encoder: &mut ffmpeg_next::codec::encoder::Video,
+    mut octx: Option<&mut format::context::Output>,
+    tiempos: &Tiempos,
+    stream_time_base: Rational,
+) -> error::Result<()> {
+    let mut packet = Packet::empty();
+    loop {
+        match encoder.receive_packet(&mut packet) {
+            Ok(_) => {
+                // En la primera de dos pasadas los paquetes se descartan
+                let Some(octx) = octx.as_deref_mut() else {
+                    continue;
+                };
+                packet.set_stream(0);
+                // Con frame rate variable cada paquete lleva su propia duración
+                if let Some(duracion) = packet.pts().and_then(|pts| tiempos.duracion_de(pts)) {
+                    packet.set_duration(duracion);
+                }
+                packet.rescale_ts(tiempos.time_base, stream_time_base);
+                packet.write_interleaved(octx).map_err(Error::ffmpeg(Etapa::Muxer))?;
+            }
+            Err(ffmpeg_next::Error::Other { errno }) if errno == 11 || errno == ffmpeg_next::sys::AVERROR(ffmpeg_next::sys::EAGAIN) => {
+                // El encoder necesita más frames antes de producir paquetes
+                break;
+            }
+            Err(ffmpeg_next::Error::Eof) => break,
+            Err(e) => return Err(Error::ffmpeg(Etapa::Codificacion)(e)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,135 @@
+// Compara el conversor nativo con una implementación de referencia en
+// punto flotante, pixel por pixel
+
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::frame;
+use image::RgbImage;
+use timelapse_lego::conversion::{self, Color, Matriz, Rango};
+
+// Imagen pseudoaleatoria reproducible
+fn imagen(width: u32, height: u32) -> RgbImage {
+    let mut semilla = 12345u32;
+    RgbImage::from_fn(width, height, |_, _| {
+        let mut canal = || {
+            semilla = semilla.wrapping_mul(1103515245).wrapping_add(12345);
+            (semilla >> 16) as u8
+        };
+        image::Rgb([canal(), canal(), canal()])
+    })
+}
+
+// Y, Cb y Cr sin redondear para un color RGB de 8 bits
+fn referencia(rgb: [f64; 3], color: Color, bits: u32) -> [f64; 3] {
+    let (kr, kb) = match color.matriz {
+        Matriz::Bt709 => (0.2126, 0.0722),
+        Matriz::Bt601 => (0.299, 0.114),
+    };
+    let escala = (1 << (bits - 8)) as f64;
+    let maximo = ((1 << bits) - 1) as f64;
+    let (luma, croma, base) = match color.rango {
+        Rango::Limitado => (219.0 * escala, 224.0 * escala, 16.0 * escala),
+        Rango::Completo => (maximo, maximo, 0.0),
+    };
+    let [r, g, b] = rgb.map(|c| c / 255.0);
+    let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+    [
+        base + luma * y,
+        128.0 * escala + croma * (b - y) / (2.0 * (1.0 - kb)),
+        128.0 * escala + croma * (r - y) / (2.0 * (1.0 - kr)),
+    ]
+}
+
+// Promedio del bloque de `ancho` x `alto` pixels que empieza en (x, y)
+fn promedio(img: &RgbImage, x: u32, y: u32, ancho: u32, alto: u32) -> [f64; 3] {
+    let mut suma = [0.0; 3];
+    let mut n = 0.0;
+    for py in y..(y + alto).min(img.height()) {
+        for px in x..(x + ancho).min(img.width()) {
+            let p = img.get_pixel(px, py);
+            for (s, c) in suma.iter_mut().zip(p.0) {
+                *s += c as f64;
+            }
+            n += 1.0;
+        }
+    }
+    suma.map(|s| s / n)
+}
+
+fn muestra(f: &frame::Video, plano: usize, x: usize, y: usize, bytes: usize) -> f64 {
+    let i = y * f.stride(plano) + x * bytes;
+    let datos = f.data(plano);
+    match bytes {
+        1 => datos[i] as f64,
+        _ => u16::from_le_bytes([datos[i], datos[i + 1]]) as f64,
+    }
+}
+
+// Convierte y verifica que cada muestra quede a menos de 1 de la referencia
+fn verificar(pixel: Pixel, width: u32, height: u32, color: Color) {
+    let img = imagen(width, height);
+    let mut f = frame::Video::new(pixel, width, height);
+    let (bits, bytes, alto_croma) = match pixel {
+        Pixel::YUV422P10LE => {
+            conversion::rgb_to_yuv422p10(&img, &mut f, width, height, color);
+            (10, 2, 1)
+        }
+        _ => {
+            conversion::rgb_to_yuv420p(&img, &mut f, width, height, color);
+            (8, 1, 2)
+        }
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let esperado = referencia(promedio(&img, x, y, 1, 1), color, bits)[0];
+            let obtenido = muestra(&f, 0, x as usize, y as usize, bytes);
+            assert!(
+                (obtenido - esperado).abs() < 1.0,
+                "{:?} Y en ({}, {}): {} en lugar de {:.2}",
+                pixel, x, y, obtenido, esperado
+            );
+        }
+    }
+    for cy in 0..height.div_ceil(alto_croma) {
+        for cx in 0..width.div_ceil(2) {
+            let esperado = referencia(promedio(&img, cx * 2, cy * alto_croma, 2, alto_croma), color, bits);
+            for (plano, esperado) in esperado.into_iter().enumerate().skip(1) {
+                let obtenido = muestra(&f, plano, cx as usize, cy as usize, bytes);
+                assert!(
+                    (obtenido - esperado).abs() < 1.0,
+                    "{:?} plano {} en ({}, {}): {} en lugar de {:.2}",
+                    pixel, plano, cx, cy, obtenido, esperado
+                );
+            }
+        }
+    }
+}
+
+const COLORES: [Color; 3] = [
+    Color { matriz: Matriz::Bt709, rango: Rango::Limitado },
+    Color { matriz: Matriz::Bt601, rango: Rango::Limitado },
+    Color { matriz: Matriz::Bt709, rango: Rango::Completo },
+];
+
+#[test]
+fn yuv420p_coincide_con_la_referencia() {
+    for color in COLORES {
+        verificar(Pixel::YUV420P, 64, 48, color);
+    }
+}
+
+#[test]
+fn yuv420p_con_dimensiones_impares() {
+    // Bordes con bloques incompletos y bandas que no llenan una tarea
+    for (width, height) in [(1, 1), (37, 23), (101, 67)] {
+        verificar(Pixel::YUV420P, width, height, COLORES[0]);
+    }
+}
+
+#[test]
+fn yuv422p10_coincide_con_la_referencia() {
+    for color in COLORES {
+        verificar(Pixel::YUV422P10LE, 64, 48, color);
+    }
+    verificar(Pixel::YUV422P10LE, 37, 23, COLORES[0]);
+}